@@ -0,0 +1,8 @@
+-- Column order matters, handlers read rows by index: name, email, age, pwd, id
+create table if not exists public.user (
+    name varchar not null,
+    email varchar not null,
+    age int not null,
+    pwd varchar not null,
+    id serial primary key
+);
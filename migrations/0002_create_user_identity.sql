@@ -0,0 +1,10 @@
+-- Accounts at upstream OpenID Connect providers linked to a local user
+create table if not exists public.user_identity (
+    id serial primary key,
+    user_id int not null references public.user (id) on delete cascade,
+    provider varchar not null,
+    subject varchar not null,
+    email varchar,
+    created_at timestamptz not null default now(),
+    unique (provider, subject)
+);
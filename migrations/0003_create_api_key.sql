@@ -0,0 +1,13 @@
+-- Personal API keys, only a sha256 of the full key is stored, the prefix is for lookup
+create table if not exists public.api_key (
+    id serial primary key,
+    user_id int not null references public.user (id) on delete cascade,
+    name varchar not null,
+    prefix varchar not null unique,
+    key_hash varchar not null,
+    scopes varchar not null default '',
+    created_at timestamptz not null default now(),
+    expires_at timestamptz,
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
@@ -0,0 +1,13 @@
+-- argon2 hashes of past passwords, the newest row also dates the current password
+create table if not exists public.password_history (
+    id serial primary key,
+    user_id int not null references public.user (id) on delete cascade,
+    pwd_hash varchar not null,
+    created_at timestamptz not null default now()
+);
+
+create index if not exists password_history_user_id_idx on public.password_history (user_id, created_at desc);
+
+-- Existing passwords start aging now, the empty hash never matches anything
+insert into public.password_history (user_id, pwd_hash)
+select id, '' from public.user;
@@ -0,0 +1,15 @@
+-- Emails identify users everywhere (sessions, tokens, lookups), make the database enforce it.
+-- Older databases may hold the same email more than once; stop with a list of them rather than
+-- a bare unique violation, the whole migration run is rolled back and retried on the next start.
+do $$
+declare
+    duplicates text;
+begin
+    select string_agg(email, ', ' order by email) into duplicates
+    from (select email from public.user group by email having count(*) > 1 limit 20) as duplicated;
+    if duplicates is not null then
+        raise exception 'cannot add user_email_key, these emails belong to more than one user: %', duplicates
+            using hint = 'merge or delete the duplicate accounts before upgrading, see "Upgrading" in the README';
+    end if;
+end $$;
+create unique index if not exists user_email_key on public.user (email);
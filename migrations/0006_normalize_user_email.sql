@@ -0,0 +1,17 @@
+-- Requests send emails trimmed and lowercase, store them the same way so that Alice@x.com and
+-- alice@x.com are one user. Accounts that only differ in case stop the migration like 0005 does.
+do $$
+declare
+    duplicates text;
+begin
+    select string_agg(normalized, ', ' order by normalized) into duplicates
+    from (select lower(trim(email)) as normalized from public.user group by 1 having count(*) > 1 limit 20) as duplicated;
+    if duplicates is not null then
+        raise exception 'cannot normalize emails, these belong to more than one user when case is ignored: %', duplicates
+            using hint = 'merge or delete the duplicate accounts before upgrading, see "Upgrading" in the README';
+    end if;
+end $$;
+update public.user set email = lower(trim(email)) where email <> lower(trim(email));
+update public.user_identity set email = lower(trim(email)) where email <> lower(trim(email));
+-- With every email in this form the unique index on email is case-insensitive too
+alter table public.user add constraint user_email_normalized check (email = lower(trim(email)));
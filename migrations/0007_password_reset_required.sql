@@ -0,0 +1,2 @@
+-- Set when an account was taken over, every session then only allows changing the password
+alter table public.user add column if not exists password_reset_required boolean not null default false;
@@ -0,0 +1,95 @@
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::config::{current, CorsConfig};
+
+// The request id is the one response header browser code needs to read
+const EXPOSED_HEADERS: &str = "x-request-id";
+
+fn allowed_origin(config: &CorsConfig, origin: &str) -> Option<HeaderValue> {
+    if config.allowed_origins.iter().any(|allowed| allowed == origin) {
+        return HeaderValue::from_str(origin).ok();
+    }
+    if config.allowed_origins.iter().any(|allowed| allowed == "*") {
+        return Some(HeaderValue::from_static("*"));
+    }
+    None
+}
+
+fn add_origin_headers(headers: &mut HeaderMap, config: &CorsConfig, origin: HeaderValue) {
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+    if config.allow_credentials {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    }
+}
+
+fn join(values: &[String]) -> HeaderValue {
+    // Validated as methods and header names, so always a valid header value
+    HeaderValue::from_str(&values.join(", ")).unwrap()
+}
+
+// Reads [cors] per request, so a reload changes the allowed origins right away. Requests
+// from origins that are not allowed get no CORS headers and the browser blocks the response.
+pub async fn cors(request: Request, next: Next) -> Response {
+    let config = match current().cors.clone() {
+        Some(config) => config,
+        None => return next.run(request).await,
+    };
+    let origin = request.headers().get(header::ORIGIN).and_then(|v| v.to_str().ok()).and_then(|o| allowed_origin(&config, o));
+    let origin = match origin {
+        Some(origin) => origin,
+        None => return next.run(request).await,
+    };
+    let preflight = request.method() == Method::OPTIONS && request.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        add_origin_headers(headers, &config, origin);
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, join(&config.allowed_methods));
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, join(&config.allowed_headers));
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(config.max_age_secs));
+        return response;
+    }
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    add_origin_headers(headers, &config, origin);
+    headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSED_HEADERS));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["authorization".to_string()],
+            allow_credentials: true,
+            max_age_secs: 600,
+        }
+    }
+
+    #[test]
+    fn test_only_listed_origins_are_allowed() {
+        let config = config();
+        assert_eq!(allowed_origin(&config, "https://app.example.com").unwrap(), "https://app.example.com");
+        assert!(allowed_origin(&config, "https://evil.example.com").is_none());
+        let any = CorsConfig { allowed_origins: vec!["*".to_string()], allow_credentials: false, ..config };
+        assert_eq!(allowed_origin(&any, "https://evil.example.com").unwrap(), "*");
+    }
+
+    #[test]
+    fn test_preflight_headers() {
+        let config = config();
+        let mut headers = HeaderMap::new();
+        add_origin_headers(&mut headers, &config, HeaderValue::from_static("https://app.example.com"));
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::VARY], "origin");
+        assert_eq!(join(&config.allowed_methods), "GET, POST");
+    }
+}
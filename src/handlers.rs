@@ -1,3 +1,7 @@
 pub mod user_handler;
-
-use user_handler::*;
\ No newline at end of file
+pub mod oauth_handler;
+pub mod oidc_handler;
+pub mod api_key_handler;
+pub mod magic_link_handler;
+pub mod email_change_handler;
+pub mod health_handler;
@@ -0,0 +1,116 @@
+use axum::extract::Path;
+use axum::{http::StatusCode, Json};
+use serde_json::json;
+use tracing::{debug, error};
+
+use crate::config::db_connection_string;
+use crate::db_connection::{execute_insert_api_key, execute_query_api_keys_by_user, execute_query_user_by_email, execute_revoke_api_key, get_db_connection, get_read_db_connection, is_unique_violation, DbConnection};
+use crate::models::api_key_models::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse};
+use crate::models::user_models::CommonResponse;
+use crate::services::api_key_service::generate_api_key;
+use crate::services::auth_service::{Authenticated, Credential};
+use crate::services::user_service::user_from_rows;
+use crate::utils::validation_util::ValidJson;
+
+const MAX_PREFIX_ATTEMPTS: u32 = 3;
+
+fn error_response(status: StatusCode, message: &str) -> (StatusCode, Json<CommonResponse>) {
+    (status, Json(CommonResponse::error(message.to_string(), json!({}))))
+}
+
+async fn owner_id(connection: &DbConnection, email: &str) -> Option<i32> {
+    let rows = execute_query_user_by_email(connection, email).await.unwrap();
+    user_from_rows(&rows).map(|user| user._id)
+}
+
+pub async fn create_api_key(
+    auth: Authenticated,
+    ValidJson(req): ValidJson<CreateApiKeyRequest>,
+) -> (StatusCode, Json<CommonResponse>) {
+    // A leaked key must not be able to mint more keys
+    if let Credential::ApiKey(key_id, _) = auth.credential {
+        debug!("[CreateApiKey]Refused for API key {}", key_id);
+        return error_response(StatusCode::FORBIDDEN, "API keys cannot create API keys");
+    }
+    let email = match auth.user() {
+        Ok(email) => email,
+        Err(rejection) => return rejection,
+    };
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    let user_id = match owner_id(&connection, email).await {
+        Some(user_id) => user_id,
+        None => return error_response(StatusCode::BAD_REQUEST, "email not found"),
+    };
+    // The 8 char prefix is unique, a clash with an existing key just means drawing another one
+    let mut attempts = 0;
+    let (generated, rows) = loop {
+        let generated = generate_api_key();
+        let inserted = execute_insert_api_key(
+            &connection,
+            &user_id,
+            &req.name,
+            &generated.prefix,
+            &generated.key_hash,
+            &req.scopes.join(" "),
+            &req.expires_in_days,
+        )
+        .await;
+        match inserted {
+            Ok(rows) => break (generated, rows),
+            Err(e) if is_unique_violation(&e) && attempts < MAX_PREFIX_ATTEMPTS => {
+                attempts += 1;
+                debug!("[CreateApiKey]Prefix {} is taken, generating another", generated.prefix);
+            }
+            Err(e) => {
+                error!("[CreateApiKey]Database error: {}", e);
+                return error_response(StatusCode::SERVICE_UNAVAILABLE, "API key could not be created, try again later");
+            }
+        }
+    };
+    let api_key = ApiKeyResponse::from_row(rows.first().unwrap());
+    debug!("[CreateApiKey]User {} created key {} ({})", user_id, api_key.id, api_key.prefix);
+    let created = CreatedApiKeyResponse { key: generated.key, api_key };
+    let response = CommonResponse::success("API key created, it will not be shown again".to_string(), json!(created));
+    (StatusCode::CREATED, Json(response))
+}
+
+pub async fn list_api_keys(auth: Authenticated) -> (StatusCode, Json<CommonResponse>) {
+    let email = match auth.user() {
+        Ok(email) => email,
+        Err(rejection) => return rejection,
+    };
+    let connection_str = db_connection_string();
+    let connection = get_read_db_connection(&connection_str).await.unwrap();
+    let user_id = match owner_id(&connection, email).await {
+        Some(user_id) => user_id,
+        None => return error_response(StatusCode::BAD_REQUEST, "email not found"),
+    };
+    let rows = execute_query_api_keys_by_user(&connection, &user_id).await.unwrap();
+    let keys: Vec<ApiKeyResponse> = rows.iter().map(ApiKeyResponse::from_row).collect();
+    let response = CommonResponse::success("API keys retrieved successfully".to_string(), json!(keys));
+    (StatusCode::OK, Json(response))
+}
+
+pub async fn revoke_api_key(
+    auth: Authenticated,
+    Path(key_id): Path<i32>,
+) -> (StatusCode, Json<CommonResponse>) {
+    let email = match auth.user() {
+        Ok(email) => email,
+        Err(rejection) => return rejection,
+    };
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    let user_id = match owner_id(&connection, email).await {
+        Some(user_id) => user_id,
+        None => return error_response(StatusCode::BAD_REQUEST, "email not found"),
+    };
+    let res = execute_revoke_api_key(&connection, &user_id, &key_id).await.unwrap();
+    if res == 1 {
+        let response = CommonResponse::success("API key revoked successfully".to_string(), json!({}));
+        (StatusCode::OK, Json(response))
+    } else {
+        error_response(StatusCode::NOT_FOUND, "API key not found")
+    }
+}
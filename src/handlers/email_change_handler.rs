@@ -0,0 +1,177 @@
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::Json;
+use serde_json::json;
+use tracing::{debug, error, info};
+
+use crate::config::{db_connection_string, email_change_config, ldap_config_for_email, redis_connection_string};
+use crate::db_connection::{execute_change_email, execute_query_user_by_email, execute_revoke_api_keys_by_user, execute_set_password_reset_required, get_db_connection, get_read_db_connection, is_unique_violation};
+use crate::models::email_change_models::{EmailChangeQuery, EmailChangeRequest, EmailChangeState};
+use crate::models::user_models::CommonResponse;
+use crate::redis_instance::RedisInstance;
+use crate::services::auth_service::{Authenticated, Credential};
+use crate::services::credential_service::{verify_credentials, CredentialError};
+use crate::services::jwt_service::{decode_link_token, issue_link_token, EMAIL_CHANGE_AUDIENCE, EMAIL_REVERT_AUDIENCE};
+use crate::services::mail_service::send_mail;
+use crate::services::session_service::end_session;
+use crate::utils::validation_util::ValidJson;
+
+fn change_key(jti: &str) -> String {
+    format!("email_change:{}", jti)
+}
+
+fn revert_key(jti: &str) -> String {
+    format!("email_revert:{}", jti)
+}
+
+fn error_response(status: StatusCode, message: &str) -> (StatusCode, Json<CommonResponse>) {
+    (status, Json(CommonResponse::error(message.to_string(), json!({}))))
+}
+
+fn spawn_mail(to: String, subject: &'static str, body: String) {
+    tokio::spawn(async move {
+        if let Err(e) = send_mail(&to, subject, &body).await {
+            error!("[EmailChange]Failed to send \"{}\": {}", subject, e);
+        }
+    });
+}
+
+// Nothing changes until the new address follows the link mailed to it
+pub async fn request_email_change(auth: Authenticated, ValidJson(req): ValidJson<EmailChangeRequest>) -> (StatusCode, Json<CommonResponse>) {
+    let config = match email_change_config() {
+        Some(config) => config,
+        None => return error_response(StatusCode::NOT_FOUND, "email change is disabled"),
+    };
+    if let Credential::ApiKey(..) = auth.credential {
+        return error_response(StatusCode::FORBIDDEN, "the email can only be changed from a login session");
+    }
+    let old_email = match auth.user() {
+        Ok(email) => email.to_string(),
+        Err(rejection) => return rejection,
+    };
+    let new_email = req.new_email.clone();
+    if new_email == old_email {
+        return error_response(StatusCode::BAD_REQUEST, "new email must differ from the current one");
+    }
+    if ldap_config_for_email(&old_email).is_some() || ldap_config_for_email(&new_email).is_some() {
+        return error_response(StatusCode::BAD_REQUEST, "accounts for this email domain are managed by the directory");
+    }
+    let user = match verify_credentials(&old_email, req.pwd.expose()).await {
+        Ok(verified) => verified.user,
+        Err(CredentialError::Backend(_)) => return error_response(StatusCode::SERVICE_UNAVAILABLE, "credential backend unavailable"),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    // Early answer only, the unique index has the final say when the link is followed
+    let connection_str = db_connection_string();
+    let connection = get_read_db_connection(&connection_str).await.unwrap();
+    if !execute_query_user_by_email(&connection, &new_email).await.unwrap().is_empty() {
+        return error_response(StatusCode::CONFLICT, "email already exists");
+    }
+
+    let (token, claims) = issue_link_token(&new_email, EMAIL_CHANGE_AUDIENCE, config.ttl);
+    let state = EmailChangeState { user_id: user._id, old_email, new_email: new_email.clone() };
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    redis.set_with_expiration(&change_key(&claims.jti), &json!(state).to_string(), config.ttl).unwrap();
+    let body = format!(
+        "Follow this link to make this the email of your account:\n\n{}?token={}\n\nIt expires in {} minutes. If you did not ask for this, ignore this mail.",
+        config.verify_url,
+        token,
+        config.ttl / 60
+    );
+    spawn_mail(new_email, "Confirm your new email", body);
+    let response = CommonResponse::success("A confirmation link has been sent to the new email".to_string(), json!({}));
+    (StatusCode::ACCEPTED, Json(response))
+}
+
+pub async fn verify_email_change(Query(query): Query<EmailChangeQuery>) -> (StatusCode, Json<CommonResponse>) {
+    let config = match email_change_config() {
+        Some(config) => config,
+        None => return error_response(StatusCode::NOT_FOUND, "email change is disabled"),
+    };
+    let claims = match decode_link_token(&query.token, EMAIL_CHANGE_AUDIENCE) {
+        Ok(claims) => claims,
+        Err(e) => {
+            debug!("[EmailChange]Invalid link: {}", e);
+            return error_response(StatusCode::UNAUTHORIZED, "invalid or expired link");
+        }
+    };
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    let state: EmailChangeState = match redis.get_del(&change_key(&claims.jti)).unwrap() {
+        Some(state) => serde_json::from_str(&state).unwrap(),
+        None => return error_response(StatusCode::UNAUTHORIZED, "link already used or expired"),
+    };
+    if state.new_email != claims.sub {
+        return error_response(StatusCode::UNAUTHORIZED, "invalid or expired link");
+    }
+
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    match execute_change_email(&connection, &state.user_id, &state.old_email, &state.new_email).await {
+        Ok(1) => {}
+        Ok(_) => return error_response(StatusCode::CONFLICT, "the account changed since this link was sent"),
+        Err(e) if is_unique_violation(&e) => return error_response(StatusCode::CONFLICT, "email already exists"),
+        Err(e) => {
+            error!("[EmailChange]{}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "email change failed");
+        }
+    }
+    // The session is keyed on the old email, sign it out rather than carry it over
+    end_session(&mut redis, &state.old_email).unwrap();
+    info!("[SecurityEvent]email_changed user={}", state.user_id);
+
+    let (token, claims) = issue_link_token(&state.old_email, EMAIL_REVERT_AUDIENCE, config.revert_ttl);
+    redis.set_with_expiration(&revert_key(&claims.jti), &json!(state).to_string(), config.revert_ttl).unwrap();
+    let body = format!(
+        "The email of your account was changed to {}.\n\nIf this was not you, follow this link to change it back and sign everyone out:\n\n{}?token={}\n\nThe link works for {} days.",
+        state.new_email,
+        config.revert_url,
+        token,
+        config.revert_ttl / (24 * 3600)
+    );
+    spawn_mail(state.old_email, "Your email was changed", body);
+    let response = CommonResponse::success("Email changed, sign in with the new address".to_string(), json!({}));
+    (StatusCode::OK, Json(response))
+}
+
+// The old address takes the account back: the email is restored and everything issued since is revoked
+pub async fn revert_email_change(Query(query): Query<EmailChangeQuery>) -> (StatusCode, Json<CommonResponse>) {
+    let claims = match decode_link_token(&query.token, EMAIL_REVERT_AUDIENCE) {
+        Ok(claims) => claims,
+        Err(e) => {
+            debug!("[EmailChange]Invalid revert link: {}", e);
+            return error_response(StatusCode::UNAUTHORIZED, "invalid or expired link");
+        }
+    };
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    let state: EmailChangeState = match redis.get_del(&revert_key(&claims.jti)).unwrap() {
+        Some(state) => serde_json::from_str(&state).unwrap(),
+        None => return error_response(StatusCode::UNAUTHORIZED, "link already used or expired"),
+    };
+    if state.old_email != claims.sub {
+        return error_response(StatusCode::UNAUTHORIZED, "invalid or expired link");
+    }
+
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    match execute_change_email(&connection, &state.user_id, &state.new_email, &state.old_email).await {
+        Ok(1) => {}
+        Ok(_) => return error_response(StatusCode::CONFLICT, "the account changed since this link was sent"),
+        Err(e) if is_unique_violation(&e) => return error_response(StatusCode::CONFLICT, "email already exists"),
+        Err(e) => {
+            error!("[EmailChange]{}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "email change failed");
+        }
+    }
+    // Whoever moved the email may also know the password: sign everything out and make the
+    // next login change it
+    end_session(&mut redis, &state.new_email).unwrap();
+    end_session(&mut redis, &state.old_email).unwrap();
+    let revoked_keys = execute_revoke_api_keys_by_user(&connection, &state.user_id).await.unwrap();
+    execute_set_password_reset_required(&connection, &state.user_id, &true).await.unwrap();
+    info!("[SecurityEvent]email_change_reverted user={} revoked_api_keys={} password_reset_required=true", state.user_id, revoked_keys);
+    let response = CommonResponse::success("Email restored, sign in and change your password".to_string(), json!({}));
+    (StatusCode::OK, Json(response))
+}
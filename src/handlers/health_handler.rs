@@ -0,0 +1,38 @@
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use crate::models::health_models::{CheckResult, HealthQuery, HealthReport};
+use crate::services::health_service::{liveness, readiness, startup};
+
+// 200 when every check passed, 503 otherwise. Plain "ok" for kubelet, the full report with ?verbose.
+fn respond(checks: Vec<CheckResult>, query: HealthQuery) -> Response {
+    let healthy = checks.iter().all(|check| check.ok);
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    if query.verbose.is_none() {
+        let failed: Vec<&str> = checks.iter().filter(|check| !check.ok).map(|check| check.name.as_str()).collect();
+        let body = if healthy { "ok".to_string() } else { format!("failed: {}", failed.join(", ")) };
+        return (status, body).into_response();
+    }
+    let report = HealthReport {
+        status: if healthy { "ok" } else { "failed" }.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        // Set by the image build, e.g. GIT_SHA=$(git rev-parse --short HEAD) cargo build
+        commit: option_env!("GIT_SHA").unwrap_or("unknown").to_string(),
+        checks,
+    };
+    (status, Json(report)).into_response()
+}
+
+pub async fn livez(Query(query): Query<HealthQuery>) -> Response {
+    respond(liveness().await, query)
+}
+
+pub async fn readyz(Query(query): Query<HealthQuery>) -> Response {
+    respond(readiness().await, query)
+}
+
+pub async fn startupz(Query(query): Query<HealthQuery>) -> Response {
+    respond(startup().await, query)
+}
@@ -0,0 +1,144 @@
+use axum::http::HeaderMap;
+use axum::{http::StatusCode, Form, Json};
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+use crate::config::{clients_config, redis_connection_string, ClientConfig};
+use crate::models::oauth_models::{IntrospectionRequest, IntrospectionResponse, OAuthError, RevocationRequest, TokenRequest, TokenResponse};
+use crate::redis_instance::RedisInstance;
+use crate::services::client_service::{authenticate_client, basic_credentials, resolve_scopes};
+use crate::services::jwt_service::{get_info_from_token, issue_client_token, revoke_token, validate_token, Principal, TokenError, ACCESS_TOKEN_TTL};
+
+// Without it a client may only revoke the tokens issued to itself
+const REVOKE_SCOPE: &str = "tokens:revoke";
+
+fn oauth_error(status: StatusCode, error: &str, description: &str) -> (StatusCode, Json<Value>) {
+    (status, Json(json!(OAuthError::new(error, description))))
+}
+
+// Authenticate the calling client with HTTP Basic, falling back to credentials in the form body
+async fn authenticated_client(
+    headers: &HeaderMap,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<ClientConfig, (StatusCode, Json<Value>)> {
+    let credentials = basic_credentials(headers).or(match (client_id, client_secret) {
+        (Some(id), Some(secret)) => Some((id, secret)),
+        _ => None,
+    });
+    let (client_id, client_secret) = match credentials {
+        Some(credentials) => credentials,
+        None => return Err(oauth_error(StatusCode::UNAUTHORIZED, "invalid_client", "client authentication required")),
+    };
+    let clients = clients_config();
+    match authenticate_client(&clients, &client_id, &client_secret) {
+        Some(client) => Ok(client.clone()),
+        None => {
+            error!("[ClientAuth]Client authentication failed: {}", client_id);
+            Err(oauth_error(StatusCode::UNAUTHORIZED, "invalid_client", "client authentication failed"))
+        }
+    }
+}
+
+pub async fn token(
+    headers: HeaderMap,
+    Form(req): Form<TokenRequest>,
+) -> (StatusCode, Json<Value>) {
+    if req.grant_type != "client_credentials" {
+        return oauth_error(StatusCode::BAD_REQUEST, "unsupported_grant_type", "only client_credentials is supported");
+    }
+    let client = match authenticated_client(&headers, req.client_id, req.client_secret).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let scopes = match resolve_scopes(&client, req.scope.as_deref()) {
+        Ok(scopes) => scopes,
+        Err(e) => return oauth_error(StatusCode::BAD_REQUEST, "invalid_scope", &e),
+    };
+    debug!("[Token]Issuing token for client {} with scopes {:?}", client.client_id, scopes);
+    let access_token = issue_client_token(&client.client_id, &scopes);
+    let response = TokenResponse::new(access_token, ACCESS_TOKEN_TTL, scopes.join(" "));
+    (StatusCode::OK, Json(json!(response)))
+}
+
+pub async fn introspect(
+    headers: HeaderMap,
+    Form(req): Form<IntrospectionRequest>,
+) -> (StatusCode, Json<Value>) {
+    let client = match authenticated_client(&headers, req.client_id, req.client_secret).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    // Only access tokens exist, so the hint does not change the lookup
+    debug!("[Introspect]Client {} introspecting token (hint: {:?})", client.client_id, req.token_type_hint);
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    let response = match validate_token(&mut redis, req.token.trim()) {
+        Ok(claims) => IntrospectionResponse::active(claims),
+        Err(TokenError::Store(e)) => {
+            error!("[Introspect]{}", e);
+            return oauth_error(StatusCode::SERVICE_UNAVAILABLE, "temporarily_unavailable", "token state unavailable");
+        }
+        Err(e) => {
+            debug!("[Introspect]Token inactive: {}", e);
+            IntrospectionResponse::inactive()
+        }
+    };
+    (StatusCode::OK, Json(json!(response)))
+}
+
+pub async fn revoke(
+    headers: HeaderMap,
+    Form(req): Form<RevocationRequest>,
+) -> (StatusCode, Json<Value>) {
+    let client = match authenticated_client(&headers, req.client_id, req.client_secret).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    // RFC 7009 section 2.1: an unknown hint is ignored, and only access tokens exist anyway
+    debug!("[Revoke]Client {} revoking token (hint: {:?})", client.client_id, req.token_type_hint);
+    let token = req.token.trim();
+    // Invalid or expired tokens are already unusable, RFC 7009 still answers 200
+    let claims = match get_info_from_token(token) {
+        Ok(claims) => claims,
+        Err(e) => {
+            debug!("[Revoke]Ignoring invalid token: {}", e);
+            return (StatusCode::OK, Json(json!({})));
+        }
+    };
+    // A client revokes its own tokens, anything else needs the revoke scope
+    let own_token = claims.principal == Principal::Client && claims.sub == client.client_id;
+    if !own_token && !client.scopes.iter().any(|s| s == REVOKE_SCOPE) {
+        error!("[Revoke]Client {} tried to revoke a token of {}", client.client_id, claims.sub);
+        return oauth_error(StatusCode::BAD_REQUEST, "unauthorized_client", "token was not issued to this client");
+    }
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    match revoke_token(&mut redis, token, &claims) {
+        Ok(()) => (StatusCode::OK, Json(json!({}))),
+        Err(e) => {
+            error!("[Revoke]Redis error: {}", e);
+            oauth_error(StatusCode::SERVICE_UNAVAILABLE, "temporarily_unavailable", "token state unavailable")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::jwt_service::issue_jwt_token;
+
+    #[tokio::test]
+    async fn test_user_tokens_need_the_revoke_scope() {
+        // internal-service from config.toml holds no scopes
+        let req = RevocationRequest {
+            token: issue_jwt_token("alice@example.com", &[], false),
+            token_type_hint: Some("id_token".to_string()),
+            client_id: Some("internal-service".to_string()),
+            client_secret: Some("internal-secret".to_string()),
+        };
+        let (status, Json(body)) = revoke(HeaderMap::new(), Form(req)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "unauthorized_client");
+    }
+}
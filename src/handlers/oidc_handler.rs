@@ -0,0 +1,127 @@
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Json;
+use serde_json::json;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::config::{db_connection_string, oidc_provider_config, redis_connection_string};
+use crate::db_connection::get_db_connection;
+use crate::metrics::metrics;
+use crate::models::oidc_models::{OidcCallbackQuery, OidcState};
+use crate::models::user_models::{CommonResponse, UserLoginResponse};
+use crate::redis_instance::RedisInstance;
+use crate::services::oidc_service::{authorization_url, discover, exchange_code, resolve_user, verify_id_token};
+use crate::services::session_service::open_session;
+use crate::utils::cookie_util::{build_cookie, get_cookie};
+
+const STATE_COOKIE: &str = "oidc_state";
+// How long the user has to finish signing in at the provider
+const STATE_TTL: u64 = 600;
+
+fn state_key(state: &str) -> String {
+    format!("oidc_state:{}", state)
+}
+
+fn error_response(status: StatusCode, message: &str) -> (StatusCode, Json<CommonResponse>) {
+    (status, Json(CommonResponse::error(message.to_string(), json!({}))))
+}
+
+pub async fn login(Path(provider_name): Path<String>) -> Response {
+    let provider = match oidc_provider_config(&provider_name) {
+        Some(provider) => provider,
+        None => return error_response(StatusCode::NOT_FOUND, "unknown identity provider").into_response(),
+    };
+    let metadata = match discover(&provider).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error!("[OidcLogin]{}", e);
+            return error_response(StatusCode::BAD_GATEWAY, "identity provider unavailable").into_response();
+        }
+    };
+    let state = Uuid::new_v4().simple().to_string();
+    let nonce = Uuid::new_v4().simple().to_string();
+    let oidc_state = OidcState { provider: provider.name.clone(), nonce };
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    redis.set_with_expiration(&state_key(&state), &json!(oidc_state).to_string(), STATE_TTL).unwrap();
+
+    let url = authorization_url(&provider, &metadata, &state, &oidc_state.nonce);
+    debug!("[OidcLogin]Redirecting to {}", provider.name);
+    // The cookie ties the callback to the browser that started the flow
+    let cookie = build_cookie(STATE_COOKIE, &state, "/oidc", STATE_TTL);
+    ([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response()
+}
+
+pub async fn callback(
+    headers: HeaderMap,
+    Path(provider_name): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> (StatusCode, Json<CommonResponse>) {
+    if let Some(e) = query.error {
+        error!("[OidcCallback]Provider returned {}: {:?}", e, query.error_description);
+        metrics().logins.with_label_values(&["oidc", "failure", "provider_error"]).inc();
+        return error_response(StatusCode::UNAUTHORIZED, "sign in was cancelled or failed");
+    }
+    let (code, state) = match (query.code, query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return error_response(StatusCode::BAD_REQUEST, "missing code or state"),
+    };
+    if get_cookie(&headers, STATE_COOKIE).as_deref() != Some(state.as_str()) {
+        return error_response(StatusCode::BAD_REQUEST, "state does not match this browser");
+    }
+    // Single use: the state is read and deleted in one step, so concurrent callbacks cannot both get it
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    let oidc_state: OidcState = match redis.get_del(&state_key(&state)) {
+        Ok(Some(value)) => match serde_json::from_str(&value) {
+            Ok(oidc_state) => oidc_state,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "state expired or already used"),
+        },
+        Ok(None) => return error_response(StatusCode::BAD_REQUEST, "state expired or already used"),
+        Err(e) => {
+            error!("[OidcCallback]Redis error: {}", e);
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "sign in is unavailable, try again later");
+        }
+    };
+    if oidc_state.provider != provider_name {
+        return error_response(StatusCode::BAD_REQUEST, "state was issued for another provider");
+    }
+
+    let provider = match oidc_provider_config(&provider_name) {
+        Some(provider) => provider,
+        None => return error_response(StatusCode::NOT_FOUND, "unknown identity provider"),
+    };
+    let claims = match discover(&provider).await {
+        Ok(metadata) => match exchange_code(&provider, &metadata, &code).await {
+            Ok(tokens) => verify_id_token(&provider, &metadata, &tokens.id_token, &oidc_state.nonce).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(e) => {
+            error!("[OidcCallback]{}", e);
+            metrics().logins.with_label_values(&["oidc", "failure", "invalid_id_token"]).inc();
+            return error_response(StatusCode::UNAUTHORIZED, "sign in with identity provider failed");
+        }
+    };
+
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    let user = match resolve_user(&connection, &provider.name, &claims).await {
+        Ok(user) => user,
+        Err(e) => {
+            error!("[OidcCallback]Could not resolve the user: {}", e);
+            metrics().logins.with_label_values(&["oidc", "failure", "identity_refused"]).inc();
+            return error_response(StatusCode::FORBIDDEN, "this identity cannot sign in here");
+        }
+    };
+    metrics().logins.with_label_values(&["oidc", "success", "none"]).inc();
+    let token = open_session(&mut redis, &user.email, &[], false).unwrap();
+    let user_login_res = UserLoginResponse::new(user._id, user.name, user.email, user.age, token);
+    let response = CommonResponse::success("User logged in successfully".to_string(), user_login_res.to_json());
+    (StatusCode::OK, Json(response))
+}
@@ -1,271 +1,342 @@
-use axum::http::HeaderMap;
-use axum::{extract::Query, http::StatusCode, response::IntoResponse, Json};
-use jsonwebtoken::errors::Error;
-use tracing::{debug,error};
-use serde_json::{json, Value, to_string};
+use axum::{http::StatusCode, Json};
+use tracing::{debug, error, info};
+use serde_json::json;
 
-use crate::models::user_models::{CommonResponse, CreateUserRequest, User, UserLoginRequest, UserLoginResponse, UserResponse, UserUpdateRequest, UserDeleteRequest};
-use crate::db_connection::{get_db_connection, execute_query_user_by_email, execute_insert_user, fetch_insert_id, execute_update_user, execute_delete_query, DbConnection};
-use crate::config::{db_connection_string,redis_connection_string};
-use crate::services::jwt_service::{issue_jwt_token, get_info_from_token};
+use crate::models::user_models::{CommonResponse, CreateUserRequest, User, UserLoginRequest, UserLoginResponse, UserResponse, UserUpdateRequest, UserDeleteRequest, PasswordChangeRequest, FieldError};
+use crate::db_connection::{get_db_connection, get_read_db_connection, execute_query_user_by_email, execute_insert_user, fetch_insert_id, execute_update_user, execute_update_password, execute_revoke_api_keys_by_user};
+use crate::config::{db_connection_string,redis_connection_string,ldap_config_for_email,password_policy_config};
+use crate::services::jwt_service::revoke_token;
+use crate::services::auth_service::{AllowExpiredPassword, Authenticated, Credential};
+use crate::services::mail_service::send_mail;
+use crate::services::session_service::open_session;
+use crate::services::password_policy_service::{validate_new_password, record_password};
+use crate::services::credential_service::{verify_credentials, CredentialError};
+use crate::metrics::metrics;
 use crate::redis_instance::RedisInstance;
+use crate::utils::password_util::hash_password_async;
+use crate::utils::validation_util::{validation_failed, CheckedJson, ValidJson};
 
 pub async fn register(
-    req: Json<CreateUserRequest>,   
+    CheckedJson(req, mut errors): CheckedJson<CreateUserRequest>,
 ) -> (StatusCode, Json<CommonResponse>) {
     debug!("Registering user: {:?}", req);
-    // Check if the email already exists
-    let connection_str: String = db_connection_string().await;
+    // Directory users are provisioned on their first login, never registered locally
+    if ldap_config_for_email(&req.email).is_some() {
+        let response = CommonResponse::error("accounts for this email domain are managed by the directory".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    // Field rules and the password policy are answered together
+    let connection_str: String = db_connection_string();
     let connection = get_db_connection(&connection_str).await.unwrap();
+    let policy = password_policy_config();
+    errors.extend(validate_new_password(&connection, &policy, None, req.pwd.expose(), &req.email, &req.name).await.unwrap());
+    if !errors.is_empty() {
+        return validation_failed(errors);
+    }
+    // Check if the email already exists
     let rows = execute_query_user_by_email(&connection, &req.email).await.unwrap();
-    if rows.len() > 0 {
+    if !rows.is_empty() {
         let response = CommonResponse::error("email already exists".to_string(), serde_json::from_str("{}").unwrap());
         return (StatusCode::BAD_REQUEST, Json(response));
     }
     // Insert the user into the database
     // Build user struct according to the request
-    let mut user = User::new(0, req.name.clone(), req.email.clone(), req.age, req.pwd.clone());
+    let mut user = User::new(0, req.name.clone(), req.email.clone(), req.age, hash_password_async(req.pwd.expose()).await);
     let rows = execute_insert_user(&connection, &user).await.unwrap();
     let id = fetch_insert_id(&rows).await.unwrap();
     user._id = id;
+    record_password(&connection, &policy, &id, req.pwd.expose()).await.unwrap();
+    metrics().registrations.inc();
 
     let response = CommonResponse::success("User created successfully".to_string(), user.to_json());
     (StatusCode::CREATED, Json(response))
 }
 
 pub async fn login(
-    req: Json<UserLoginRequest>,
+    ValidJson(req): ValidJson<UserLoginRequest>,
 ) -> (StatusCode, Json<CommonResponse>) {
-        // Check if the username exists
-        let connection_str = db_connection_string().await;
-        let connection = get_db_connection(&connection_str).await.unwrap();
-        let rows = execute_query_user_by_email(&connection, &req.email).await.unwrap();
-        if rows.len() == 0 {
-            let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("{}").unwrap());
-            return (StatusCode::BAD_REQUEST, Json(response));
-        }
-        if rows.len() > 1 {
-            let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("{}").unwrap());
-            return (StatusCode::BAD_REQUEST, Json(response));
-        }
-        // Check if the password is correct
-        let row = rows.get(0).unwrap();
-        let user = User::new(
-            row.get(4),
-            row.get(0),
-            row.get(1),
-            row.get(2),
-            row.get(3),
-        );
-        if user.pwd != req.pwd {
-            let response = CommonResponse::error("incorrect password".to_string(), serde_json::from_str("{}").unwrap());
-            return (StatusCode::BAD_REQUEST, Json(response));
-        }
-        let redis_connection_str = redis_connection_string().await;
+        // Check the password with the backend for this email: the pwd column or a directory
+        let verified = match verify_credentials(&req.email, req.pwd.expose()).await {
+            Ok(verified) => verified,
+            Err(CredentialError::Backend(_)) => {
+                metrics().logins.with_label_values(&["password", "failure", "backend_unavailable"]).inc();
+                let response = CommonResponse::error("credential backend unavailable".to_string(), serde_json::from_str("{}").unwrap());
+                return (StatusCode::SERVICE_UNAVAILABLE, Json(response));
+            }
+            Err(e) => {
+                metrics().logins.with_label_values(&["password", "failure", e.reason()]).inc();
+                let response = CommonResponse::error(e.to_string(), serde_json::from_str("{}").unwrap());
+                return (StatusCode::BAD_REQUEST, Json(response));
+            }
+        };
+        let reason = if verified.password_expired { "password_expired" } else { "none" };
+        metrics().logins.with_label_values(&["password", "success", reason]).inc();
+        let message = if verified.password_expired {
+            "Password expired, change it to continue"
+        } else {
+            "User logged in successfully"
+        };
+        let user = verified.user;
+        let redis_connection_str = redis_connection_string();
         let mut redis = RedisInstance::new(&redis_connection_str);
-        let is_exist = redis.exists(&user.email).unwrap();
-        debug!("is_exist: {:?}", is_exist);
-        if is_exist {
-            let token = redis.get(&user.email).unwrap();
-            let user_login_res = UserLoginResponse::new(user._id, user.name, user.email, user.age, token);
-            let response = CommonResponse::success("User logged in successfully".to_string(), user_login_res.to_json());
-            return (StatusCode::OK, Json(response));
-        }
-
-        // exchange the user for a token
-        let token = issue_jwt_token(&user.email, &user.pwd);
-        // write into redis
-
-        redis.set_with_expiration(&user.email, &token, 3600).unwrap();
+        let token = open_session(&mut redis, &user.email, &verified.roles, verified.password_expired).unwrap();
         // build the response
         let user_login_res = UserLoginResponse::new(user._id, user.name, user.email, user.age, token);
-        let response = CommonResponse::success("User logged in successfully".to_string(), user_login_res.to_json());
+        let response = CommonResponse::success(message.to_string(), user_login_res.to_json());
         (StatusCode::OK, Json(response))
 }
 
-pub async fn user_info (headers: HeaderMap) -> (StatusCode, Json<CommonResponse>) {
-    let token = headers.get("authorization").unwrap().to_str().unwrap();
-    let token = token.replace("Bearer ", "");
-    let token = token.trim();
-    let token_data = get_info_from_token(&token);
-    match token_data {
-        Ok(token_data) => {
-            let mail = token_data.email;
-            let connection_str = db_connection_string().await;
-            let connection = get_db_connection(&connection_str).await.unwrap();
-            let rows = execute_query_user_by_email(&connection, &mail).await.unwrap();
-            if rows.len() == 0 {
-                let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-            if rows.len() > 1 {
-                let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-            let row = rows.get(0).unwrap();
-            let user_response = UserResponse::new(
-                row.get(4),
-                row.get(0),
-                row.get(1),
-                row.get(2),
-                token_data.exp as i64,
-                token_data.iat as i64,
-                token_data.iss,
-                token_data.typ,
-            );
+fn missing_scope(scope: &str) -> (StatusCode, Json<CommonResponse>) {
+    let response = CommonResponse::error(format!("missing scope {}", scope), json!({}));
+    (StatusCode::FORBIDDEN, Json(response))
+}
 
-            let response = CommonResponse::success("User info retrieved successfully".to_string(), serde_json::json!(user_response));
-            (StatusCode::OK, Json(response))
-        }
-        Err(e) => {
-            error!("[UserInfo]Error: {:?}", e);
-            let response = CommonResponse::error("Invalid token".to_string(), serde_json::from_str("{}").unwrap());
-            (StatusCode::UNAUTHORIZED, Json(response))
-        }
+pub async fn user_info(auth: Authenticated) -> (StatusCode, Json<CommonResponse>) {
+    let mail = match auth.user() {
+        Ok(mail) => mail.to_string(),
+        Err(rejection) => return rejection,
+    };
+    if !auth.has_scope("users:read") {
+        return missing_scope("users:read");
+    }
+    let token_data = auth.claims;
+    let connection_str = db_connection_string();
+    let connection = get_read_db_connection(&connection_str).await.unwrap();
+    let rows = execute_query_user_by_email(&connection, &mail).await.unwrap();
+    if rows.is_empty() {
+        let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    if rows.len() > 1 {
+        let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
     }
+    let row = rows.first().unwrap();
+    let user_response = UserResponse::new(
+        row.get(4),
+        row.get(0),
+        row.get(1),
+        row.get(2),
+        token_data.exp as i64,
+        token_data.iat as i64,
+        token_data.iss,
+        token_data.typ,
+    );
+
+    let response = CommonResponse::success("User info retrieved successfully".to_string(), serde_json::json!(user_response));
+    (StatusCode::OK, Json(response))
 }
 
-pub async fn logout(headers: HeaderMap) -> (StatusCode, Json<CommonResponse>) {
-    let token = headers.get("authorization").unwrap().to_str().unwrap();
-    let token = token.replace("Bearer ", "");
-    let token = token.trim();
-    let token_data = get_info_from_token(&token);
-    match token_data {
-        Ok(token_data) => {
-            let mail = token_data.email;
-            let redis_connection_str = redis_connection_string().await;
-            let mut redis = RedisInstance::new(&redis_connection_str);
-            let is_exist = redis.exists(&mail).unwrap();
-            if is_exist {
-                redis.del(&mail).unwrap();
-                let response = CommonResponse::success("User logged out successfully".to_string(), serde_json::from_str("{}").unwrap());
-                (StatusCode::OK, Json(response))
-            } else {
-                let response = CommonResponse::error("User not found".to_string(), serde_json::from_str("{}").unwrap());
-                (StatusCode::BAD_REQUEST, Json(response))
-            }
-        }
-        Err(e) => {
-            let response = CommonResponse::error("Invalid token".to_string(), serde_json::from_str("{}").unwrap());
-            (StatusCode::UNAUTHORIZED, Json(response))
+pub async fn logout(auth: Authenticated) -> (StatusCode, Json<CommonResponse>) {
+    let token = match &auth.credential {
+        Credential::Bearer(token) => token,
+        Credential::ApiKey(..) => {
+            let response = CommonResponse::error("API keys are revoked, not logged out".to_string(), json!({}));
+            return (StatusCode::BAD_REQUEST, Json(response));
         }
-    }
+    };
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    // The extractor already checked the session exists, revoking also ends it
+    revoke_token(&mut redis, token, &auth.claims).unwrap();
+    let response = CommonResponse::success("User logged out successfully".to_string(), serde_json::from_str("{}").unwrap());
+    (StatusCode::OK, Json(response))
 }
 
 pub async fn update_user_info(
-    headers: HeaderMap,
-    req: Json<UserUpdateRequest>,
+    auth: Authenticated,
+    ValidJson(req): ValidJson<UserUpdateRequest>,
 ) -> (StatusCode, Json<CommonResponse>) {
-    let token = match headers.get("authorization").and_then(|v| v.to_str().ok()) {
-        Some(token) => token.replace("Bearer ", "").trim().to_string(),
-        None => {
-            let response = CommonResponse::error("Authorization token missing or invalid".to_string(), json!({}));
-            return (StatusCode::UNAUTHORIZED, Json(response));
-        }
-    };    
-    let token = token.trim();
-    let token_data = get_info_from_token(&token);
-    match token_data {
-        Ok(token_data) => {
-            let mail = token_data.email;
-            let connection_str = db_connection_string().await;
-            let connection = get_db_connection(&connection_str).await.unwrap();
-            let rows = execute_query_user_by_email(&connection, &mail).await.unwrap();
-            if rows.len() == 0 {
-                let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("{}").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-            if rows.len() > 1 {
-                let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("{}").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-            let row = rows.get(0).unwrap();
-            let mut user = User::new(
-                row.get(4),
-                row.get(0),
-                row.get(1),
-                row.get(2),
-                row.get(3),
-            );
-            user.name = req.name.clone();
-            user.age = req.age;
-            let res = execute_update_user(&connection, &user).await.unwrap();
-            if res == 1 {
-                let response = CommonResponse::success("User info updated successfully".to_string(), user.to_json());
+    let mail = match auth.user() {
+        Ok(mail) => mail.to_string(),
+        Err(rejection) => return rejection,
+    };
+    if !auth.has_scope("users:write") {
+        return missing_scope("users:write");
+    }
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    let rows = execute_query_user_by_email(&connection, &mail).await.unwrap();
+    if rows.is_empty() {
+        let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    if rows.len() > 1 {
+        let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    let row = rows.first().unwrap();
+    let mut user = User::new(
+        row.get(4),
+        row.get(0),
+        row.get(1),
+        row.get(2),
+        row.get(3),
+    );
+    user.name = req.name.clone();
+    user.age = req.age;
+    let res = execute_update_user(&connection, &user).await.unwrap();
+    if res == 1 {
+        let response = CommonResponse::success("User info updated successfully".to_string(), user.to_json());
+        (StatusCode::OK, Json(response))
+    }
+    else {
+        let response = CommonResponse::error("User info update failed".to_string(), serde_json::from_str("{}").unwrap());
+        (StatusCode::BAD_REQUEST, Json(response))
+    }
+}
+
+pub async fn delete_user(
+    auth: Authenticated,
+    ValidJson(req): ValidJson<UserDeleteRequest>,
+) -> (StatusCode, Json<CommonResponse>) {
+    let mail = match auth.user() {
+        Ok(mail) => mail.to_string(),
+        Err(rejection) => return rejection,
+    };
+    if !auth.has_scope("users:write") {
+        return missing_scope("users:write");
+    }
+    let connection_str = db_connection_string();
+    let mut connection = get_db_connection(&connection_str).await.unwrap();
+    let rows = execute_query_user_by_email(&connection, &mail).await.unwrap();
+    if rows.is_empty() {
+        let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    if rows.len() > 1 {
+        let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    let row = rows.first().unwrap();
+    let user = User::new(
+        row.get(4),
+        row.get(0),
+        row.get(1),
+        row.get(2),
+        row.get(3),
+    );
+    if let Err(e) = verify_credentials(&user.email, req.pwd.expose()).await {
+        let response = CommonResponse::error(e.to_string(), serde_json::from_str("{}").unwrap());
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+
+    let res = connection.execute_delete_query_with_rollback(&user._id).await;
+    match &res {
+        Ok(rows_affected) => {
+            if *rows_affected == 1 {
+                let response = CommonResponse::success("User deleted successfully".to_string(), serde_json::from_str("{}").unwrap());
                 (StatusCode::OK, Json(response))
-            }
-            else {
-                let response = CommonResponse::error("User info update failed".to_string(), serde_json::from_str("{}").unwrap());
+            } else {
+                let response = CommonResponse::error("User deletion failed".to_string(), serde_json::from_str("{}").unwrap());
                 (StatusCode::BAD_REQUEST, Json(response))
             }
         }
-        Err(e) => {
-            let response = CommonResponse::error("Invalid token".to_string(), serde_json::from_str("{}").unwrap());
-            (StatusCode::UNAUTHORIZED, Json(response))
+        Err(_) => {
+            let response = CommonResponse::error("User deletion failed".to_string(), serde_json::from_str("{}").unwrap());
+            (StatusCode::BAD_REQUEST, Json(response))
         }
     }
 }
 
-pub async fn delete_user(
-    headers: HeaderMap,
-    req: Json<UserDeleteRequest>,
+pub async fn change_password(
+    AllowExpiredPassword(auth): AllowExpiredPassword,
+    ValidJson(req): ValidJson<PasswordChangeRequest>,
 ) -> (StatusCode, Json<CommonResponse>) {
-    let token = match headers.get("authorization").and_then(|v| v.to_str().ok()) {
-        Some(token) => token.replace("Bearer ", "").trim().to_string(),
-        None => {
-            let response = CommonResponse::error("Authorization token missing or invalid".to_string(), json!({}));
-            return (StatusCode::UNAUTHORIZED, Json(response));
+    let token = match &auth.credential {
+        Credential::Bearer(token) => token,
+        Credential::ApiKey(..) => {
+            let response = CommonResponse::error("the password can only be changed from a login session".to_string(), json!({}));
+            return (StatusCode::FORBIDDEN, Json(response));
         }
-    };    
-    let token = token.trim();
-    let token_data = get_info_from_token(&token);
-    match token_data {
-        Ok(token_data) => {
-            let mail = token_data.email;
-            let connection_str = db_connection_string().await;
-            let mut connection = get_db_connection(&connection_str).await.unwrap();
-            let rows = execute_query_user_by_email(&connection, &mail).await.unwrap();
-            if rows.len() == 0 {
-                let response = CommonResponse::error("email not found".to_string(), serde_json::from_str("{}").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-            if rows.len() > 1 {
-                let response = CommonResponse::error("multiple users found".to_string(), serde_json::from_str("{}").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-            let row = rows.get(0).unwrap();
-            let user = User::new(
-                row.get(4),
-                row.get(0),
-                row.get(1),
-                row.get(2),
-                row.get(3),
-            );
-            if user.pwd != req.pwd {
-                let response = CommonResponse::error("incorrect password".to_string(), serde_json::from_str("{}").unwrap());
-                return (StatusCode::BAD_REQUEST, Json(response));
-            }
-         
-            let res = connection.execute_delete_query_with_rollback(&user._id).await;
-            match &res {
-                Ok(rows_affected) => {
-                    if *rows_affected == 1 {
-                        let response = CommonResponse::success("User deleted successfully".to_string(), serde_json::from_str("{}").unwrap());
-                        (StatusCode::OK, Json(response))
-                    } else {
-                        let response = CommonResponse::error("User deletion failed".to_string(), serde_json::from_str("{}").unwrap());
-                        (StatusCode::BAD_REQUEST, Json(response))
-                    }
-                }
-                Err(e) => {
-                    let response = CommonResponse::error("User deletion failed".to_string(), serde_json::from_str("{}").unwrap());
-                    (StatusCode::BAD_REQUEST, Json(response))
-                }
-            }
+    };
+    let mail = match auth.user() {
+        Ok(mail) => mail.to_string(),
+        Err(rejection) => return rejection,
+    };
+    if ldap_config_for_email(&mail).is_some() {
+        let response = CommonResponse::error("passwords for this email domain are managed by the directory".to_string(), json!({}));
+        return (StatusCode::BAD_REQUEST, Json(response));
+    }
+    let user = match verify_credentials(&mail, req.current_pwd.expose()).await {
+        Ok(verified) => verified.user,
+        Err(CredentialError::Backend(_)) => {
+            let response = CommonResponse::error("credential backend unavailable".to_string(), json!({}));
+            return (StatusCode::SERVICE_UNAVAILABLE, Json(response));
         }
         Err(e) => {
-            let response = CommonResponse::error("Invalid token".to_string(), serde_json::from_str("{}").unwrap());
-            (StatusCode::UNAUTHORIZED, Json(response))
+            let response = CommonResponse::error(e.to_string(), json!({}));
+            return (StatusCode::BAD_REQUEST, Json(response));
+        }
+    };
+    let connection_str = db_connection_string();
+    let connection = get_db_connection(&connection_str).await.unwrap();
+    let policy = password_policy_config();
+    let mut errors = validate_new_password(&connection, &policy, Some(user._id), req.new_pwd.expose(), &user.email, &user.name).await.unwrap();
+    if req.new_pwd == req.current_pwd {
+        errors.push(FieldError::new("new_pwd", "unchanged", "must differ from the current password".to_string()));
+    }
+    if !errors.is_empty() {
+        for e in errors.iter_mut() {
+            e.field = "new_pwd".to_string();
         }
+        return validation_failed(errors);
     }
-}
\ No newline at end of file
+    execute_update_password(&connection, &user._id, &hash_password_async(req.new_pwd.expose()).await).await.unwrap();
+    record_password(&connection, &policy, &user._id, req.new_pwd.expose()).await.unwrap();
+
+    // Everything issued with the old password goes: the session, its token and every API key
+    let revoked_keys = execute_revoke_api_keys_by_user(&connection, &user._id).await.unwrap();
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    revoke_token(&mut redis, token, &auth.claims).unwrap();
+    let token = open_session(&mut redis, &user.email, &[], false).unwrap();
+
+    info!("[SecurityEvent]password_changed user={} revoked_api_keys={}", user._id, revoked_keys);
+    let email = user.email.clone();
+    tokio::spawn(async move {
+        let body = "The password of your account was just changed and all other sessions were signed out.\n\nIf this was not you, reset your password right away and contact support.";
+        if let Err(e) = send_mail(&email, "Your password was changed", body).await {
+            error!("[PasswordChange]Failed to send notification: {}", e);
+        }
+    });
+
+    let user_login_res = UserLoginResponse::new(user._id, user.name, user.email, user.age, token);
+    let response = CommonResponse::success("Password changed successfully".to_string(), user_login_res.to_json());
+    (StatusCode::OK, Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::jwt_service::{get_info_from_token, issue_client_token};
+    use axum::body::Body;
+    use axum::extract::{FromRequest, Request};
+
+    #[tokio::test]
+    async fn test_client_token_cannot_read_user_info() {
+        let scopes = vec!["users:read".to_string()];
+        let token = issue_client_token("internal-service", &scopes);
+        let claims = get_info_from_token(&token).unwrap();
+        let auth = Authenticated { claims, credential: Credential::Bearer(token) };
+        let (status, Json(response)) = user_info(auth).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(response.message, "machine clients have no user account");
+    }
+
+    #[tokio::test]
+    async fn test_field_and_policy_errors_share_one_response() {
+        let body = json!({ "name": "Alice", "email": "not-an-email", "age": 30, "pwd": "short" });
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let checked = CheckedJson::<CreateUserRequest>::from_request(request, &()).await.unwrap();
+        let (status, Json(response)) = register(checked).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body = serde_json::to_value(response).unwrap();
+        let fields: Vec<&str> = body["data"]["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert!(fields.contains(&"email") && fields.contains(&"pwd"), "{}", body);
+    }
+}
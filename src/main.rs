@@ -1,11 +1,17 @@
 use axum::{
-    routing::{get, post},
+    http::StatusCode,
+    middleware,
+    routing::{delete, get, post},
     Router
 };
 
+use clap::Parser;
 use std::io;
-use std::net::SocketAddr;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::{reload, Layer};
 
 mod handlers;
 mod models;
@@ -14,28 +20,120 @@ mod config;
 mod services;
 mod utils;
 mod redis_instance;
+mod secrets;
+mod server;
+mod metrics;
+mod telemetry;
+mod logging;
+mod rate_limit;
+mod cors;
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(Level::DEBUG)
-        .with_writer(io::stdout)
+    let cli = config::Cli::parse();
+    let effective = match config::build_config(cli.config.as_deref(), &cli.overrides) {
+        Ok(effective) => effective,
+        Err(errors) => {
+            eprintln!("invalid configuration:");
+            for e in errors {
+                eprintln!("  - {}", e);
+            }
+            std::process::exit(2);
+        }
+    };
+    if cli.command == Some(config::Command::PrintConfig) {
+        print!("{}", config::redacted_toml(&effective));
+        return;
+    }
+    // Initialize tracing, the filter follows log.level across reloads unless RUST_LOG is set
+    let (filter, filter_handle) = reload::Layer::new(logging::env_filter(&effective.log.level));
+    // log.level only filters stdout, the exporter takes this crate's spans at info and above
+    let otel = match effective.tracing.as_ref().map(telemetry::layer).transpose() {
+        Ok(otel) => otel.map(|layer| layer.with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::INFO))),
+        Err(e) => {
+            eprintln!("[Tracing]Cannot set up the OTLP exporter: {}", e);
+            std::process::exit(2);
+        }
+    };
+    tracing_subscriber::registry()
+        .with(logging::layer(effective.log.format, io::stdout).with_filter(filter))
+        .with(otel)
         .init();
+    config::init(effective, cli.config, cli.overrides);
+    config::spawn_reloader();
+    let mut changes = config::subscribe();
+    tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            let level = changes.borrow_and_update().log.level.clone();
+            if let Err(e) = filter_handle.reload(logging::env_filter(&level)) {
+                tracing::error!("[ConfigReload]Failed to apply log.level {}: {}", level, e);
+            }
+        }
+    });
+
+    // Bring the schema up to date before serving
+    let connection_str = config::db_connection_string();
+    let mut connection = db_connection::get_db_connection(&connection_str).await.unwrap();
+    if let Err(e) = db_connection::run_migrations(&mut connection).await {
+        tracing::error!("[Migration]Failed, no migration of this run was applied: {}", e);
+        std::process::exit(1);
+    }
+    db_connection::spawn_replica_health_checker();
 
     // Initialize the router
     let app = Router::new()
-    .route("/hb", get(|| async { "OK" }))
+    .route("/hb", get(|| async {
+        if server::shutting_down() {
+            (StatusCode::SERVICE_UNAVAILABLE, "SHUTTING_DOWN")
+        } else {
+            (StatusCode::OK, "OK")
+        }
+    }))
+    .route("/livez", get(handlers::health_handler::livez))
+    .route("/readyz", get(handlers::health_handler::readyz))
+    .route("/startupz", get(handlers::health_handler::startupz))
     .route("/register", post(handlers::user_handler::register))
     .route("/login", post(handlers::user_handler::login))
+    .route("/login/magic", post(handlers::magic_link_handler::request_magic_link))
+    .route("/login/magic/verify", get(handlers::magic_link_handler::verify_magic_link))
     .route("/user_info", get(handlers::user_handler::user_info))
     .route("/logout",post(handlers::user_handler::logout))
     .route("/update_info", post(handlers::user_handler::update_user_info))
-    .route("/delete_user", post(handlers::user_handler::delete_user));
+    .route("/delete_user", post(handlers::user_handler::delete_user))
+    .route("/password/change", post(handlers::user_handler::change_password))
+    .route("/email/change", post(handlers::email_change_handler::request_email_change))
+    .route("/email/change/verify", get(handlers::email_change_handler::verify_email_change))
+    .route("/email/change/revert", get(handlers::email_change_handler::revert_email_change))
+    .route("/token", post(handlers::oauth_handler::token))
+    .route("/introspect", post(handlers::oauth_handler::introspect))
+    .route("/revoke", post(handlers::oauth_handler::revoke))
+    .route("/oidc/:provider/login", get(handlers::oidc_handler::login))
+    .route("/oidc/:provider/callback", get(handlers::oidc_handler::callback))
+    .route("/api_keys", get(handlers::api_key_handler::list_api_keys).post(handlers::api_key_handler::create_api_key))
+    .route("/api_keys/:id", delete(handlers::api_key_handler::revoke_api_key))
+    .layer(middleware::from_fn(db_connection::request_scope))
+    .layer(middleware::from_fn(rate_limit::limit))
+    .layer(middleware::from_fn(metrics::track_http))
+    .layer(middleware::from_fn(logging::access_log))
+    .layer(middleware::from_fn(cors::cors))
+    .layer(middleware::from_fn(telemetry::trace_http))
+    .layer(middleware::from_fn(telemetry::assign_request_id));
+
+    // Metrics stay off the public port, only scrapers reaching the pod see them
+    let metrics_config = config::current().metrics.clone();
+    let metrics_listener = config::ListenerConfig { bind: metrics_config.bind, port: metrics_config.port, tls: None };
+    let metrics_app = Router::new().route("/metrics", get(metrics::render));
+    tokio::spawn(async move {
+        if let Err(e) = server::serve(metrics_app, metrics_listener, axum_server::Handle::new()).await {
+            tracing::error!("[Metrics]Listener failed: {}", e);
+        }
+    });
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    tracing::debug!("listening on {}", addr);
-    axum::serve(listener, app).await.unwrap();
-    
-}
\ No newline at end of file
+    let listener = config::current().listener.clone();
+    let handle = axum_server::Handle::new();
+    server::spawn_graceful_shutdown(handle.clone());
+    server::serve(app, listener, handle).await.unwrap();
+    // Database and Redis connections are per request, the drain already closed them
+    telemetry::shutdown().await;
+    tracing::info!("[Shutdown]Stopped");
+}
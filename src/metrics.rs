@@ -0,0 +1,141 @@
+use axum::extract::{MatchedPath, Request};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// Everything /metrics exposes. Labels stay low-cardinality: route templates, function names
+// and fixed reasons, never user input.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_connections: IntCounterVec,
+    pub db_connect_duration: HistogramVec,
+    pub db_open_connections: IntGaugeVec,
+    pub db_query_duration: HistogramVec,
+    pub db_query_errors: IntCounterVec,
+    pub redis_command_duration: HistogramVec,
+    pub redis_command_errors: IntCounterVec,
+    pub registrations: IntCounter,
+    pub logins: IntCounterVec,
+    pub tokens_issued: IntCounterVec,
+    pub tokens_revoked: IntCounter,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+fn counter_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric");
+    registry.register(Box::new(counter.clone())).expect("metric registered once");
+    counter
+}
+
+fn histogram_vec(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let histogram = HistogramVec::new(HistogramOpts::new(name, help), labels).expect("valid metric");
+    registry.register(Box::new(histogram.clone())).expect("metric registered once");
+    histogram
+}
+
+fn counter(registry: &Registry, name: &str, help: &str) -> IntCounter {
+    let counter = IntCounter::new(name, help).expect("valid metric");
+    registry.register(Box::new(counter.clone())).expect("metric registered once");
+    counter
+}
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| {
+        let registry = Registry::new();
+        let db_open_connections = IntGaugeVec::new(Opts::new("db_open_connections", "Postgres connections currently open"), &["target"]).expect("valid metric");
+        registry.register(Box::new(db_open_connections.clone())).expect("metric registered once");
+        Metrics {
+            http_requests: counter_vec(&registry, "http_requests_total", "HTTP requests by matched route and status", &["method", "route", "status"]),
+            http_request_duration: histogram_vec(&registry, "http_request_duration_seconds", "HTTP request latency", &["method", "route", "status"]),
+            db_connections: counter_vec(&registry, "db_connections_total", "Postgres connection attempts", &["target", "result"]),
+            db_connect_duration: histogram_vec(&registry, "db_connect_duration_seconds", "Time to open a Postgres connection", &["target"]),
+            db_open_connections,
+            db_query_duration: histogram_vec(&registry, "db_query_duration_seconds", "Postgres query latency by repository function", &["query"]),
+            db_query_errors: counter_vec(&registry, "db_query_errors_total", "Failed Postgres queries by repository function", &["query"]),
+            redis_command_duration: histogram_vec(&registry, "redis_command_duration_seconds", "Redis command latency", &["command"]),
+            redis_command_errors: counter_vec(&registry, "redis_command_errors_total", "Failed Redis commands", &["command"]),
+            registrations: counter(&registry, "registrations_total", "Accounts registered"),
+            logins: counter_vec(&registry, "logins_total", "Logins by method, result and failure reason", &["method", "result", "reason"]),
+            tokens_issued: counter_vec(&registry, "tokens_issued_total", "Tokens issued by kind", &["kind"]),
+            tokens_revoked: counter(&registry, "tokens_revoked_total", "Tokens revoked"),
+            registry,
+        }
+    })
+}
+
+// Times a Postgres query and counts its failures under the repository function's name
+pub async fn observe_query<T, E>(query: &'static str, future: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let timer = metrics().db_query_duration.with_label_values(&[query]).start_timer();
+    let result = future.await;
+    timer.observe_duration();
+    if result.is_err() {
+        metrics().db_query_errors.with_label_values(&[query]).inc();
+    }
+    result
+}
+
+// Same for Redis, whose client is blocking
+pub fn observe_command<T, E>(command: &'static str, run: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let timer = metrics().redis_command_duration.with_label_values(&[command]).start_timer();
+    let result = run();
+    timer.observe_duration();
+    if result.is_err() {
+        metrics().redis_command_errors.with_label_values(&[command]).inc();
+    }
+    result
+}
+
+// Labels by the route template (/api_keys/:id), requests no route matched share one label
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string()).unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    metrics().http_requests.with_label_values(&labels).inc();
+    metrics().http_request_duration.with_label_values(&labels).observe(started.elapsed().as_secs_f64());
+    response
+}
+
+// Prometheus text exposition format
+pub async fn render() -> Response {
+    let mut body = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&metrics().registry.gather(), &mut body) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::middleware;
+    use axum::routing::get;
+    use axum::Router;
+
+    #[tokio::test]
+    async fn test_requests_are_labelled_by_route() {
+        let app = Router::new()
+            .route("/metrics_test/:id", get(|| async { "OK" }))
+            .route("/metrics", get(render))
+            .layer(middleware::from_fn(track_http));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        reqwest::get(format!("{}/metrics_test/1", base)).await.unwrap();
+        reqwest::get(format!("{}/metrics_test/2", base)).await.unwrap();
+        reqwest::get(format!("{}/metrics_test_missing", base)).await.unwrap();
+        let body = reqwest::get(format!("{}/metrics", base)).await.unwrap().text().await.unwrap();
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/metrics_test/:id",status="200"} 2"#), "{}", body);
+        assert!(body.contains(r#"route="unmatched",status="404""#));
+    }
+}
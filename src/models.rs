@@ -1 +1,7 @@
-pub mod user_models;
\ No newline at end of file
+pub mod user_models;
+pub mod oauth_models;
+pub mod oidc_models;
+pub mod api_key_models;
+pub mod magic_link_models;
+pub mod email_change_models;
+pub mod health_models;
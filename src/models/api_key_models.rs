@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+use validator::{Validate, ValidationError};
+
+use crate::services::api_key_service::unknown_scopes;
+use crate::utils::validation_util::trimmed;
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct CreateApiKeyRequest {
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    pub name: String,
+    #[serde(default)]
+    #[validate(custom(function = "known_scopes"))]
+    pub scopes: Vec<String>,
+    // Never expires when absent
+    #[validate(range(min = 1, max = 3650, message = "must be between 1 and 3650"))]
+    pub expires_in_days: Option<i32>,
+}
+
+fn known_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    let unknown = unknown_scopes(scopes);
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    Err(ValidationError::new("scopes").with_message(format!("unknown scopes: {}", unknown.join(", ")).into()))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiKeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+impl ApiKeyResponse {
+    pub fn from_row(row: &Row) -> ApiKeyResponse {
+        let scopes: String = row.get(3);
+        ApiKeyResponse {
+            id: row.get(0),
+            name: row.get(1),
+            prefix: row.get(2),
+            scopes: scopes.split_whitespace().map(|s| s.to_string()).collect(),
+            created_at: row.get(4),
+            expires_at: row.get(5),
+            last_used_at: row.get(6),
+        }
+    }
+}
+
+// The only response that ever contains the key itself
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
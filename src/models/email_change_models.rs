@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::secrets::Secret;
+use crate::utils::validation_util::normalized_email;
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct EmailChangeRequest {
+    #[serde(deserialize_with = "normalized_email")]
+    #[validate(email(message = "must be a valid email address"), length(max = 254, message = "must be at most 254 characters"))]
+    pub new_email: String,
+    pub pwd: Secret,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmailChangeQuery {
+    pub token: String,
+}
+
+// Kept in Redis under the link's jti, for the verification link and later the revert link
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmailChangeState {
+    pub user_id: i32,
+    pub old_email: String,
+    pub new_email: String,
+}
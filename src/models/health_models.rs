@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+// ?verbose switches a probe from a bare status line to the JSON report
+#[derive(Deserialize, Debug)]
+pub struct HealthQuery {
+    pub verbose: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: String,
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Answered from the cache instead of probing the dependency again
+    pub cached: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HealthReport {
+    pub status: String,
+    pub version: String,
+    pub commit: String,
+    pub checks: Vec<CheckResult>,
+}
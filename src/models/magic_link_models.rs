@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::utils::validation_util::normalized_email;
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct MagicLinkRequest {
+    #[serde(deserialize_with = "normalized_email")]
+    #[validate(email(message = "must be a valid email address"))]
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MagicLinkQuery {
+    pub token: String,
+}
+
+// Kept in Redis under the link's jti until the link is used or expires
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MagicLinkState {
+    pub email: String,
+    // sha256 of the binding cookie given to the requesting browser
+    pub binding_hash: String,
+}
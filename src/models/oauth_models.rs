@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use crate::services::jwt_service::{Claims, Principal};
+use crate::telemetry::request_id;
+
+// RFC 6749 section 4.4.2, sent as application/x-www-form-urlencoded
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    pub scope: String,
+}
+
+impl TokenResponse {
+    pub fn new(access_token: String, expires_in: u64, scope: String) -> TokenResponse {
+        TokenResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in,
+            scope,
+        }
+    }
+}
+
+// RFC 7662 section 2.1
+#[derive(Deserialize)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+// RFC 7009 section 2.1
+#[derive(Deserialize)]
+pub struct RevocationRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+// RFC 7662 section 2.2, an inactive token carries nothing but `active: false`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    // Extension: "user" or "client"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub principal: Option<Principal>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> IntrospectionResponse {
+        IntrospectionResponse::default()
+    }
+
+    pub fn active(claims: Claims) -> IntrospectionResponse {
+        let (client_id, username) = match claims.principal {
+            Principal::Client => (Some(claims.sub.clone()), None),
+            Principal::User => (None, Some(claims.email)),
+        };
+        IntrospectionResponse {
+            active: true,
+            scope: Some(claims.scope).filter(|s| !s.is_empty()),
+            client_id,
+            username,
+            token_type: Some("Bearer".to_string()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            sub: Some(claims.sub),
+            iss: Some(claims.iss),
+            jti: Some(claims.jti).filter(|s| !s.is_empty()),
+            principal: Some(claims.principal),
+        }
+    }
+}
+
+// RFC 6749 section 5.2 error body
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OAuthError {
+    pub error: String,
+    pub error_description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl OAuthError {
+    pub fn new(error: &str, error_description: &str) -> OAuthError {
+        OAuthError {
+            error: error.to_string(),
+            error_description: error_description.to_string(),
+            request_id: request_id(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inactive_introspection_has_only_active() {
+        let body = serde_json::to_value(IntrospectionResponse::inactive()).unwrap();
+        assert_eq!(body, serde_json::json!({ "active": false }));
+    }
+}
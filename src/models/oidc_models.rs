@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+// Kept in Redis between the redirect and the callback
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OidcState {
+    pub provider: String,
+    pub nonce: String,
+}
+
+// The subset of the discovery document we use
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OidcTokenResponse {
+    pub id_token: String,
+    pub access_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub exp: u64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::fmt;
+use validator::Validate;
+
+use crate::secrets::Secret;
+use crate::telemetry::request_id;
+use crate::utils::validation_util::{normalized_email, trimmed};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
@@ -7,6 +13,8 @@ pub struct User {
     pub name: String,
     pub email: String,
     pub age: i32,
+    // The stored hash, never part of a response
+    #[serde(skip_serializing)]
     pub pwd: String,
 }
 
@@ -20,14 +28,17 @@ impl User {
             pwd,
         }
     }
-    pub fn to_string(&self) -> String {
-        format!("User: id: {}, name: {}, email: {}, age: {}", self._id, self.name, self.email, self.age)
-    }
     pub fn to_json(&self) -> serde_json::Value {
         serde_json::json!(self)
     }
 }
 
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "User: id: {}, name: {}, email: {}, age: {}", self._id, self.name, self.email, self.age)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserResponse {
     pub id: i32,
@@ -41,6 +52,7 @@ pub struct UserResponse {
 }
 
 impl UserResponse {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(id: i32, name: String, email: String, age: i32, exp: i64, iat: i64, iss: String, typ: String) -> UserResponse {
         UserResponse {
             id,
@@ -55,18 +67,25 @@ impl UserResponse {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct CreateUserRequest {
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
     pub name: String,
+    #[serde(deserialize_with = "normalized_email")]
+    #[validate(email(message = "must be a valid email address"), length(max = 254, message = "must be at most 254 characters"))]
     pub email: String,
+    #[validate(range(min = 0, max = 150, message = "must be between 0 and 150"))]
     pub age: i32,
-    pub pwd: String,
+    // Checked against the password policy, never trimmed
+    pub pwd: Secret,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct UserLoginRequest {
+    #[serde(deserialize_with = "normalized_email")]
     pub email: String,
-    pub pwd: String,
+    pub pwd: Secret,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -88,39 +107,53 @@ impl UserLoginResponse {
             token,
         }
     }
-    pub fn to_string(&self) -> String {
-        format!("User: id: {}, name: {}, email: {}, age: {}, token: {}", self.id, self.name, self.email, self.age, self.token)
-    }
-    pub fn to_json_string(&self) -> String {
-        serde_json::to_string(&self).unwrap()
-    }
     pub fn to_json(&self) -> serde_json::Value {
         serde_json::json!(self)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Claims {
-    pub sub: String,
-    pub iat: u64,
-    pub exp: u64,
-    pub iss: String,
-    pub typ: String,
-    pub email: String,
-    pub name: String,
-    pub age: i32,
-    pub id: i32,
+impl fmt::Display for UserLoginResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "User: id: {}, name: {}, email: {}, age: {}, token: {}", self.id, self.name, self.email, self.age, self.token)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct UserUpdateRequest {
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
     pub name: String,
+    #[validate(range(min = 0, max = 150, message = "must be between 0 and 150"))]
     pub age: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
 pub struct UserDeleteRequest {
-    pub pwd: String,
+    pub pwd: Secret,
+}
+
+// Why one field of a request was rejected
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub rule: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, rule: &str, message: String) -> FieldError {
+        FieldError {
+            field: field.to_string(),
+            rule: rule.to_string(),
+            message,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct PasswordChangeRequest {
+    pub current_pwd: Secret,
+    pub new_pwd: Secret,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -143,6 +176,9 @@ pub struct CommonResponse {
     pub code: String,
     #[serde(flatten)] // 使用 flatten 属性将 data 字段扁平化
     pub data: Data,
+    // X-Request-Id of the request answered, to quote when reporting a problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl CommonResponse {
@@ -152,6 +188,7 @@ impl CommonResponse {
             message,
             code,
             data,
+            request_id: request_id(),
         }
     }
 
@@ -160,7 +197,8 @@ impl CommonResponse {
         CommonResponse {
             message,
             code: "200".to_string(),
-            data: data,
+            data,
+            request_id: request_id(),
         }
     }
 
@@ -170,6 +208,20 @@ impl CommonResponse {
             message,
             code: "500".to_string(),
             data,
+            request_id: request_id(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_response_has_no_password_hash() {
+        let user = User::new(7, "Alice".to_string(), "alice@example.com".to_string(), 30, "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA".to_string());
+        let body = serde_json::to_value(CommonResponse::success("User created successfully".to_string(), user.to_json())).unwrap();
+        assert_eq!(body["data"]["email"], "alice@example.com");
+        assert!(body["data"].get("pwd").is_none(), "{}", body);
+    }
+}
@@ -0,0 +1,127 @@
+use axum::extract::{ConnectInfo, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tracing::debug;
+
+use crate::config::{current, RateLimitConfig};
+use crate::models::user_models::CommonResponse;
+
+// Probes come from the kubelet and must never be throttled
+const EXEMPT_ROUTES: &[&str] = &["/hb", "/livez", "/readyz", "/startupz"];
+// Past this many tracked clients, the ones whose bucket has refilled are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, config: &RateLimitConfig, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.requests_per_second).min(config.burst as f64);
+        self.updated = now;
+    }
+}
+
+static BUCKETS: OnceLock<Mutex<HashMap<IpAddr, Bucket>>> = OnceLock::new();
+
+fn buckets() -> &'static Mutex<HashMap<IpAddr, Bucket>> {
+    BUCKETS.get_or_init(Default::default)
+}
+
+// Takes a token from the client's bucket, or returns the seconds until the next one
+fn take(buckets: &mut HashMap<IpAddr, Bucket>, client: IpAddr, config: &RateLimitConfig, now: Instant) -> Result<(), u64> {
+    if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+        buckets.retain(|_, bucket| {
+            bucket.refill(config, now);
+            bucket.tokens < config.burst as f64
+        });
+    }
+    let bucket = buckets.entry(client).or_insert(Bucket { tokens: config.burst as f64, updated: now });
+    bucket.refill(config, now);
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        return Ok(());
+    }
+    Err(((1.0 - bucket.tokens) / config.requests_per_second).ceil() as u64)
+}
+
+// The address forwarded_for_hops proxies back, each proxy appends the address it saw to X-Forwarded-For
+fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, hops: usize) -> Option<IpAddr> {
+    if hops == 0 {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded.len().checked_sub(hops).and_then(|i| forwarded[i].parse().ok()).or(peer)
+}
+
+// Reads [rate_limit] per request, so a reload changes the limits right away
+pub async fn limit(request: Request, next: Next) -> Response {
+    let config = match current().rate_limit.clone() {
+        Some(config) => config,
+        None => return next.run(request).await,
+    };
+    if EXEMPT_ROUTES.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+    let client = match client_ip(request.headers(), peer, config.forwarded_for_hops) {
+        Some(client) => client,
+        None => return next.run(request).await,
+    };
+    let taken = take(&mut buckets().lock().unwrap(), client, &config, Instant::now());
+    match taken {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            debug!("[RateLimit]Throttled {}", client);
+            let response = CommonResponse::error("too many requests, slow down".to_string(), json!({}));
+            (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], Json(response)).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bucket_allows_a_burst_then_refills() {
+        let config = RateLimitConfig { requests_per_second: 2.0, burst: 3, forwarded_for_hops: 0 };
+        let mut buckets = HashMap::new();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(take(&mut buckets, client, &config, start), Ok(()));
+        }
+        assert_eq!(take(&mut buckets, client, &config, start), Err(1));
+        // Another client has its own bucket
+        assert_eq!(take(&mut buckets, "10.0.0.2".parse().unwrap(), &config, start), Ok(()));
+        assert_eq!(take(&mut buckets, client, &config, start + Duration::from_millis(500)), Ok(()));
+    }
+
+    #[test]
+    fn test_client_ip_counts_proxy_hops() {
+        let peer: IpAddr = "10.1.0.9".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 203.0.113.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(peer), 0), Some(peer));
+        assert_eq!(client_ip(&headers, Some(peer), 1), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(client_ip(&headers, Some(peer), 2), Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(client_ip(&headers, Some(peer), 3), Some(peer));
+    }
+}
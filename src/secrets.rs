@@ -0,0 +1,275 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config::VaultConfig;
+
+const REDACTED: &str = "<redacted>";
+
+// A sensitive config value. Debug and Serialize never show it, only expose() does.
+// In the config file it is either the value itself or a reference like "file:/path",
+// "env:NAME" or "vault:path#key", which is swapped for the value it points to when the config is built.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Secret {
+        Secret(value.to_string())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    UnknownScheme(String),
+    Fetch(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretError::UnknownScheme(scheme) => write!(f, "no secret provider for \"{}:\" references", scheme),
+            SecretError::Fetch(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Looks up the reference part of "<scheme>:<reference>"
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    fn scheme(&self) -> &'static str;
+    async fn fetch(&self, reference: &str) -> Result<String, SecretError>;
+}
+
+// A mounted file, e.g. a Kubernetes Secret volume. One trailing newline is dropped.
+pub struct FileProvider;
+
+#[async_trait]
+impl SecretProvider for FileProvider {
+    fn scheme(&self) -> &'static str {
+        "file"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String, SecretError> {
+        let contents = tokio::fs::read_to_string(reference)
+            .await
+            .map_err(|e| SecretError::Fetch(format!("file {}: {}", reference, e)))?;
+        let value = contents.strip_suffix('\n').unwrap_or(&contents);
+        Ok(value.strip_suffix('\r').unwrap_or(value).to_string())
+    }
+}
+
+pub struct EnvProvider;
+
+#[async_trait]
+impl SecretProvider for EnvProvider {
+    fn scheme(&self) -> &'static str {
+        "env"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String, SecretError> {
+        std::env::var(reference).map_err(|e| SecretError::Fetch(format!("env {}: {}", reference, e)))
+    }
+}
+
+// Secret data read from Vault by its URL, kept across config reloads for refresh_secs
+static VAULT_CACHE: OnceLock<Mutex<HashMap<String, (Instant, Value)>>> = OnceLock::new();
+
+fn vault_cache() -> &'static Mutex<HashMap<String, (Instant, Value)>> {
+    VAULT_CACHE.get_or_init(Default::default)
+}
+
+// HashiCorp Vault KV version 2, references are "<path>#<key>" below the configured mount
+pub struct VaultProvider {
+    config: VaultConfig,
+    client: reqwest::Client,
+}
+
+impl VaultProvider {
+    pub fn new(config: VaultConfig) -> VaultProvider {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs.max(1)))
+            .build()
+            .expect("Unable to build the Vault client");
+        VaultProvider { config, client }
+    }
+
+    async fn read(&self, url: &str) -> Result<Value, reqwest::Error> {
+        let refresh = Duration::from_secs(self.config.refresh_secs);
+        if let Some((read_at, body)) = vault_cache().lock().unwrap().get(url) {
+            if read_at.elapsed() < refresh {
+                return Ok(body.clone());
+            }
+        }
+        let body: Value = self.client
+            .get(url)
+            .headers(crate::telemetry::trace_headers())
+            .header("X-Vault-Token", self.config.token.expose())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        vault_cache().lock().unwrap().insert(url.to_string(), (Instant::now(), body.clone()));
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultProvider {
+    fn scheme(&self) -> &'static str {
+        "vault"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String, SecretError> {
+        let (path, key) = reference
+            .split_once('#')
+            .ok_or_else(|| SecretError::Fetch(format!("vault {}: expected <path>#<key>", reference)))?;
+        let url = format!("{}/v1/{}/data/{}", self.config.addr.trim_end_matches('/'), self.config.mount, path);
+        let fail = |e: String| SecretError::Fetch(format!("vault {}: {}", path, e));
+        let body = self.read(&url).await.map_err(|e| fail(e.to_string()))?;
+        match &body["data"]["data"][key] {
+            Value::String(value) => Ok(value.clone()),
+            Value::Null => Err(fail(format!("no key {}", key))),
+            other => Ok(other.to_string()),
+        }
+    }
+}
+
+pub struct SecretResolver {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl SecretResolver {
+    pub fn new(providers: Vec<Box<dyn SecretProvider>>) -> SecretResolver {
+        SecretResolver { providers }
+    }
+
+    // Values without a known "<scheme>:" prefix are literal
+    pub async fn resolve(&self, raw: &str) -> Result<String, SecretError> {
+        let (scheme, reference) = match raw.split_once(':') {
+            Some((scheme, reference)) if is_scheme(scheme) => (scheme, reference),
+            _ => return Ok(raw.to_string()),
+        };
+        match self.providers.iter().find(|p| p.scheme() == scheme) {
+            Some(provider) => provider.fetch(reference).await,
+            // Only complain about schemes we know of, a password may well contain a colon
+            None if ["file", "env", "vault"].contains(&scheme) => Err(SecretError::UnknownScheme(scheme.to_string())),
+            None => Ok(raw.to_string()),
+        }
+    }
+}
+
+fn is_scheme(scheme: &str) -> bool {
+    !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Path;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::{routing::get, Json, Router};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static READS: AtomicUsize = AtomicUsize::new(0);
+
+    // Serves KV v2 reads like `vault server -dev` would, for the token "dev-token"
+    async fn mock_vault() -> VaultConfig {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/v1/secret/data/*path", get(|headers: HeaderMap, Path(path): Path<String>| async move {
+            if headers.get("x-vault-token").map(|v| v.as_bytes()) != Some(b"dev-token".as_slice()) {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            match path.as_str() {
+                "counter" => Ok(Json(json!({ "data": { "data": { "reads": READS.fetch_add(1, Ordering::SeqCst) + 1 } } }))),
+                "rust-on-k8s" => Ok(Json(json!({ "data": { "data": { "db_password": "from-vault" }, "metadata": { "version": 1 } } }))),
+                _ => Err(StatusCode::NOT_FOUND),
+            }
+        }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        VaultConfig { addr, token: Secret::new("dev-token"), mount: "secret".to_string(), timeout_secs: 5, refresh_secs: 300 }
+    }
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"<redacted>\"");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_references() {
+        let path = std::env::temp_dir().join(format!("secret-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let resolver = SecretResolver::new(vec![Box::new(FileProvider), Box::new(EnvProvider), Box::new(VaultProvider::new(mock_vault().await))]);
+
+        assert_eq!(resolver.resolve(&format!("file:{}", path.display())).await.unwrap(), "from-file");
+        assert_eq!(resolver.resolve("env:PATH").await.unwrap(), std::env::var("PATH").unwrap());
+        assert_eq!(resolver.resolve("vault:rust-on-k8s#db_password").await.unwrap(), "from-vault");
+        assert!(resolver.resolve("vault:rust-on-k8s#missing").await.is_err());
+        assert!(resolver.resolve("vault:other#db_password").await.is_err());
+        // Literals, including ones that merely contain a colon
+        assert_eq!(resolver.resolve("plain").await.unwrap(), "plain");
+        assert_eq!(resolver.resolve("Pa:ss").await.unwrap(), "Pa:ss");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_vault_reads_are_reused_until_refresh() {
+        let mut config = mock_vault().await;
+        let cached = VaultProvider::new(config.clone());
+        let first = cached.fetch("counter#reads").await.unwrap();
+        assert_eq!(VaultProvider::new(config.clone()).fetch("counter#reads").await.unwrap(), first);
+        config.refresh_secs = 0;
+        assert_ne!(VaultProvider::new(config).fetch("counter#reads").await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn test_unconfigured_provider_is_an_error() {
+        let resolver = SecretResolver::new(vec![Box::new(FileProvider)]);
+        assert!(matches!(resolver.resolve("vault:a#b").await, Err(SecretError::UnknownScheme(_))));
+    }
+
+    // Start one with `vault server -dev -dev-root-token-id=dev-token`, then
+    // `vault kv put secret/rust-on-k8s db_password=from-vault`
+    #[tokio::test]
+    #[ignore = "requires a local Vault dev server"]
+    async fn test_vault_dev_server() {
+        let config = VaultConfig { addr: "http://127.0.0.1:8200".to_string(), token: Secret::new("dev-token"), mount: "secret".to_string(), timeout_secs: 5, refresh_secs: 0 };
+        let provider = VaultProvider::new(config);
+        assert_eq!(provider.fetch("rust-on-k8s#db_password").await.unwrap(), "from-vault");
+    }
+}
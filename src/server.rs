@@ -0,0 +1,236 @@
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+use crate::config::{current, ListenerConfig, ShutdownConfig, TlsConfig};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+// True from the first SIGTERM or SIGINT on, health checks then fail so no new traffic arrives
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+// Serves the app on the configured address, over TLS when listener.tls is set
+pub async fn serve(app: Router, listener: ListenerConfig, handle: Handle) -> io::Result<()> {
+    let bind: IpAddr = listener.bind.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("listener.bind: {}", e)))?;
+    let addr = SocketAddr::new(bind, listener.port);
+    // The peer address is what rate limiting keys on without forwarded_for_hops
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    match listener.tls {
+        None => {
+            info!("[Server]Listening on http://{}", addr);
+            axum_server::bind(addr).handle(handle).serve(service).await
+        }
+        Some(tls) => {
+            let (server_config, files) = load_tls(&tls).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let rustls_config = RustlsConfig::from_config(Arc::new(server_config));
+            spawn_certificate_reloader(rustls_config.clone(), files);
+            info!("[Server]Listening on https://{}{}", addr, if tls.client_ca.is_some() { " with client certificates" } else { "" });
+            axum_server::bind_rustls(addr, rustls_config).handle(handle).serve(service).await
+        }
+    }
+}
+
+// SIGTERM is what the kubelet sends, SIGINT is Ctrl-C when running locally
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("[Shutdown]Received SIGINT"),
+        _ = terminate.recv() => info!("[Shutdown]Received SIGTERM"),
+    }
+}
+
+// serve returns once the drain is over
+pub fn spawn_graceful_shutdown(handle: Handle) {
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        drain(handle, current().shutdown.clone()).await;
+    });
+}
+
+// Waits out the pre-stop delay while the Service stops routing to us, then stops accepting
+// and gives in-flight requests until the drain deadline
+pub async fn drain(handle: Handle, shutdown: ShutdownConfig) {
+    info!("[Shutdown]Failing health checks, closing the listener in {}s", shutdown.pre_stop_delay_secs);
+    tokio::time::sleep(Duration::from_secs(shutdown.pre_stop_delay_secs)).await;
+    info!("[Shutdown]Draining {} connections for up to {}s", handle.connection_count(), shutdown.drain_timeout_secs);
+    handle.graceful_shutdown(Some(Duration::from_secs(shutdown.drain_timeout_secs)));
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn certificates(path: &str, pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = rustls_pemfile::certs(&mut &pem[..]).collect::<Result<Vec<_>, _>>().map_err(|e| format!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path));
+    }
+    Ok(certs)
+}
+
+// Builds the rustls config from the PEM files, also returning their contents to notice changes
+pub fn load_tls(tls: &TlsConfig) -> Result<(ServerConfig, Vec<Vec<u8>>), String> {
+    let cert_pem = read(&tls.cert)?;
+    let key_pem = read(&tls.key)?;
+    let certs = certificates(&tls.cert, &cert_pem)?;
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut &key_pem[..])
+        .map_err(|e| format!("{}: {}", tls.key, e))?
+        .ok_or_else(|| format!("{}: no private key found", tls.key))?;
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let mut files = vec![cert_pem, key_pem];
+    let builder = match &tls.client_ca {
+        Some(path) => {
+            let ca_pem = read(path)?;
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(path, &ca_pem)? {
+                roots.add(cert).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            files.push(ca_pem);
+            let mut verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            if tls.client_auth_optional {
+                verifier = verifier.allow_unauthenticated();
+            }
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_single_cert(certs, key).map_err(|e| e.to_string())?;
+    // Offer HTTP/2, falling back to HTTP/1.1
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok((server_config, files))
+}
+
+// cert-manager and kubelet replace the mounted files in place, pick them up without a restart.
+// A broken update (e.g. key and certificate out of step mid-rotation) keeps the current certificate.
+fn spawn_certificate_reloader(rustls_config: RustlsConfig, mut files: Vec<Vec<u8>>) {
+    let interval = current().reload.interval_secs;
+    if interval == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        let mut last_error = String::new();
+        loop {
+            ticker.tick().await;
+            let tls = match current().listener.tls.clone() {
+                Some(tls) => tls,
+                None => continue,
+            };
+            match load_tls(&tls) {
+                Ok((_, latest)) if latest == files => last_error.clear(),
+                Ok((server_config, latest)) => {
+                    rustls_config.reload_from_config(Arc::new(server_config));
+                    files = latest;
+                    last_error.clear();
+                    info!("[Server]Reloaded TLS certificate");
+                }
+                Err(e) if e != last_error => {
+                    error!("[Server]Keeping the current TLS certificate: {}", e);
+                    last_error = e;
+                }
+                Err(_) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    fn testdata(name: &str) -> String {
+        format!("{}/src/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn tls_config(client_ca: bool) -> TlsConfig {
+        TlsConfig {
+            cert: testdata("server.pem"),
+            key: testdata("server-key.pem"),
+            client_ca: client_ca.then(|| testdata("ca.pem")),
+            client_auth_optional: false,
+        }
+    }
+
+    // Serves /hb on a free port and returns its address
+    async fn start(tls: TlsConfig) -> SocketAddr {
+        let handle = Handle::new();
+        let listener = ListenerConfig { bind: "127.0.0.1".to_string(), port: 0, tls: Some(tls) };
+        let app = Router::new().route("/hb", get(|| async { "OK" }));
+        tokio::spawn(serve(app, listener, handle.clone()));
+        handle.listening().await.unwrap()
+    }
+
+    fn client(identity: bool) -> reqwest::Client {
+        let ca = reqwest::Certificate::from_pem(&std::fs::read(testdata("ca.pem")).unwrap()).unwrap();
+        let mut builder = reqwest::Client::builder().use_rustls_tls().add_root_certificate(ca);
+        if identity {
+            let mut pem = std::fs::read(testdata("client.pem")).unwrap();
+            pem.extend(std::fs::read(testdata("client-key.pem")).unwrap());
+            builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drain_finishes_in_flight_requests() {
+        let handle = Handle::new();
+        let listener = ListenerConfig { bind: "127.0.0.1".to_string(), port: 0, tls: None };
+        let app = Router::new().route("/slow", get(|| async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            "done"
+        }));
+        let server = tokio::spawn(serve(app, listener, handle.clone()));
+        let url = format!("http://{}/slow", handle.listening().await.unwrap());
+        let in_flight = tokio::spawn(reqwest::get(url.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        drain(handle, ShutdownConfig { pre_stop_delay_secs: 0, drain_timeout_secs: 5 }).await;
+        assert_eq!(in_flight.await.unwrap().unwrap().text().await.unwrap(), "done");
+        server.await.unwrap().unwrap();
+        assert!(reqwest::get(url).await.is_err());
+    }
+
+    #[test]
+    fn test_load_tls_reports_the_broken_file() {
+        let (server_config, files) = load_tls(&tls_config(true)).unwrap();
+        assert_eq!(server_config.alpn_protocols, vec![b"h2".to_vec(), b"http/1.1".to_vec()]);
+        assert_eq!(files.len(), 3);
+        let broken = TlsConfig { key: testdata("ca.pem"), ..tls_config(false) };
+        assert!(load_tls(&broken).unwrap_err().contains("no private key"));
+    }
+
+    #[tokio::test]
+    async fn test_tls_negotiates_http2() {
+        let addr = start(tls_config(false)).await;
+        let response = client(false).get(format!("https://localhost:{}/hb", addr.port())).send().await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert_eq!(response.text().await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_is_required() {
+        let addr = start(tls_config(true)).await;
+        let url = format!("https://localhost:{}/hb", addr.port());
+        assert!(client(false).get(&url).send().await.is_err());
+        assert_eq!(client(true).get(&url).send().await.unwrap().status(), 200);
+    }
+}
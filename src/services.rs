@@ -1 +1,11 @@
-pub mod jwt_service;
\ No newline at end of file
+pub mod jwt_service;
+pub mod client_service;
+pub mod session_service;
+pub mod oidc_service;
+pub mod user_service;
+pub mod credential_service;
+pub mod api_key_service;
+pub mod auth_service;
+pub mod mail_service;
+pub mod password_policy_service;
+pub mod health_service;
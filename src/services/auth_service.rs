@@ -0,0 +1,208 @@
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::{async_trait, Json};
+use serde_json::json;
+use std::fmt;
+use tracing::{debug, error};
+
+use crate::config::{db_connection_string, redis_connection_string};
+use crate::db_connection::get_db_connection;
+use crate::logging::record_caller;
+use crate::models::user_models::CommonResponse;
+use crate::redis_instance::RedisInstance;
+use crate::services::api_key_service::authenticate_api_key;
+use crate::services::jwt_service::{validate_token, Claims, Principal, TokenError};
+
+// How the caller proved who they are
+pub enum Credential {
+    Bearer(String),
+    // The key id and the id of the user who owns it
+    ApiKey(i32, i32),
+}
+
+// The one authentication layer for protected routes: a bearer JWT or an X-API-Key header.
+// API keys are presented as claims too, so handlers do not care which one was used.
+pub struct Authenticated {
+    pub claims: Claims,
+    pub credential: Credential,
+}
+
+impl Authenticated {
+    // Interactive user sessions may do everything the user may, everything else is limited to its scopes
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match self.credential {
+            Credential::Bearer(_) if self.claims.principal == Principal::User => true,
+            _ => self.claims.scope.split_whitespace().any(|s| s == scope),
+        }
+    }
+
+    // The email of the user behind the call, machine clients have no account of their own
+    pub fn user(&self) -> Result<&str, (StatusCode, Json<CommonResponse>)> {
+        match self.claims.principal {
+            Principal::User => Ok(&self.claims.email),
+            Principal::Client => {
+                debug!("[Authenticate]Client {} called a user account route", self.claims.sub);
+                let response = CommonResponse::error("machine clients have no user account".to_string(), json!({}));
+                Err((StatusCode::FORBIDDEN, Json(response)))
+            }
+        }
+    }
+
+    // Who is calling, for logs: never the email, only ids that need a lookup to lead to a person
+    pub fn log_id(&self) -> String {
+        match (&self.credential, &self.claims.principal) {
+            (Credential::ApiKey(_, user_id), _) => format!("user:{}", user_id),
+            (Credential::Bearer(_), Principal::User) => format!("session:{}", self.claims.jti),
+            (Credential::Bearer(_), Principal::Client) => format!("client:{}", self.claims.sub),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Missing,
+    Token(TokenError),
+    InvalidApiKey,
+    PasswordExpired,
+    Db(tokio_postgres::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "Authorization token missing or invalid"),
+            AuthError::Token(_) => write!(f, "Invalid token"),
+            AuthError::InvalidApiKey => write!(f, "Invalid API key"),
+            AuthError::PasswordExpired => write!(f, "Password expired, change it to continue"),
+            AuthError::Db(_) => write!(f, "Authentication unavailable"),
+        }
+    }
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get("authorization")?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        return None;
+    }
+    Some(token.to_string())
+}
+
+pub async fn authenticate(headers: &HeaderMap) -> Result<Authenticated, AuthError> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        let connection_str = db_connection_string();
+        let connection = get_db_connection(&connection_str).await.map_err(AuthError::Db)?;
+        let owner = authenticate_api_key(&connection, key.trim())
+            .await
+            .map_err(AuthError::Db)?
+            .ok_or(AuthError::InvalidApiKey)?;
+        let claims = Claims {
+            sub: owner.email.clone(),
+            iat: owner.created_at as u64,
+            exp: owner.expires_at.unwrap_or(0) as u64,
+            iss: "ColonD".to_string(),
+            typ: "api_key".to_string(),
+            email: owner.email,
+            principal: Principal::User,
+            scope: owner.scopes,
+            jti: String::new(),
+            roles: Vec::new(),
+            password_expired: false,
+        };
+        let auth = Authenticated { claims, credential: Credential::ApiKey(owner.key_id, owner.user_id) };
+        record_caller(&auth.log_id());
+        return Ok(auth);
+    }
+    let token = bearer_token(headers).ok_or(AuthError::Missing)?;
+    let redis_connection_str = redis_connection_string();
+    let mut redis = RedisInstance::new(&redis_connection_str);
+    let claims = validate_token(&mut redis, &token).map_err(AuthError::Token)?;
+    let auth = Authenticated { claims, credential: Credential::Bearer(token) };
+    record_caller(&auth.log_id());
+    Ok(auth)
+}
+
+fn reject(e: AuthError) -> (StatusCode, Json<CommonResponse>) {
+    match &e {
+        AuthError::Db(inner) => error!("[Authenticate]Database error: {}", inner),
+        AuthError::Token(inner) => debug!("[Authenticate]{}", inner),
+        _ => debug!("[Authenticate]{}", e),
+    }
+    let status = match e {
+        AuthError::Db(_) | AuthError::Token(TokenError::Store(_)) => StatusCode::SERVICE_UNAVAILABLE,
+        AuthError::PasswordExpired => StatusCode::FORBIDDEN,
+        _ => StatusCode::UNAUTHORIZED,
+    };
+    (status, Json(CommonResponse::error(e.to_string(), json!({}))))
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Authenticated {
+    type Rejection = (StatusCode, Json<CommonResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let auth = authenticate(&parts.headers).await.map_err(reject)?;
+        // A session opened with an expired password may only change it
+        if auth.claims.password_expired {
+            return Err(reject(AuthError::PasswordExpired));
+        }
+        Ok(auth)
+    }
+}
+
+// Like Authenticated, but also accepts sessions whose password has expired
+pub struct AllowExpiredPassword(pub Authenticated);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AllowExpiredPassword {
+    type Rejection = (StatusCode, Json<CommonResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        authenticate(&parts.headers).await.map(AllowExpiredPassword).map_err(reject)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(principal: Principal) -> Claims {
+        Claims {
+            sub: "Colon D Face :)".to_string(),
+            iat: 0,
+            exp: 0,
+            iss: "ColonD".to_string(),
+            typ: "guest".to_string(),
+            email: "alice@example.com".to_string(),
+            principal,
+            scope: String::new(),
+            jti: String::new(),
+            roles: Vec::new(),
+            password_expired: false,
+        }
+    }
+
+    #[test]
+    fn test_log_id_names_the_caller_without_email() {
+        let mut user_claims = claims(Principal::User);
+        user_claims.jti = "3f2a".to_string();
+        let user = Authenticated { claims: user_claims, credential: Credential::Bearer("t".to_string()) };
+        assert_eq!(user.log_id(), "session:3f2a");
+        let api_key = Authenticated { claims: claims(Principal::User), credential: Credential::ApiKey(3, 42) };
+        assert_eq!(api_key.log_id(), "user:42");
+        let mut client_claims = claims(Principal::Client);
+        client_claims.sub = "internal-service".to_string();
+        client_claims.email = String::new();
+        let client = Authenticated { claims: client_claims, credential: Credential::Bearer("t".to_string()) };
+        assert_eq!(client.log_id(), "client:internal-service");
+    }
+
+    #[test]
+    fn test_clients_have_no_user_account() {
+        let user = Authenticated { claims: claims(Principal::User), credential: Credential::Bearer("t".to_string()) };
+        assert_eq!(user.user().unwrap(), "alice@example.com");
+        let client = Authenticated { claims: claims(Principal::Client), credential: Credential::Bearer("t".to_string()) };
+        assert_eq!(client.user().unwrap_err().0, StatusCode::FORBIDDEN);
+    }
+}
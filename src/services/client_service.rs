@@ -0,0 +1,76 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use axum::http::HeaderMap;
+
+use crate::config::ClientConfig;
+use crate::utils::sha256_util::hash_sha256;
+
+// Client credentials from an `Authorization: Basic` header, RFC 6749 section 2.3.1
+pub fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get("authorization")?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?.trim();
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+pub fn authenticate_client<'a>(clients: &'a [ClientConfig], client_id: &str, client_secret: &str) -> Option<&'a ClientConfig> {
+    let secret_hash = hash_sha256(client_secret);
+    clients
+        .iter()
+        .find(|c| c.client_id == client_id && c.secret_hash.eq_ignore_ascii_case(&secret_hash))
+}
+
+// Narrow the requested scopes to the ones the client is allowed, or grant all of them if none were asked for
+pub fn resolve_scopes(client: &ClientConfig, requested: Option<&str>) -> Result<Vec<String>, String> {
+    let requested: Vec<&str> = match requested {
+        Some(scope) => scope.split_whitespace().collect(),
+        None => Vec::new(),
+    };
+    if requested.is_empty() {
+        return Ok(client.scopes.clone());
+    }
+    match requested.iter().find(|s| !client.scopes.iter().any(|allowed| allowed == *s)) {
+        Some(scope) => Err(format!("scope {} is not allowed for this client", scope)),
+        None => Ok(requested.iter().map(|s| s.to_string()).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> ClientConfig {
+        ClientConfig {
+            client_id: "internal-service".to_string(),
+            secret_hash: hash_sha256("internal-secret"),
+            scopes: vec!["reports:read".to_string(), "reports:write".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_authenticate_client() {
+        let clients = vec![client()];
+        assert!(authenticate_client(&clients, "internal-service", "internal-secret").is_some());
+        assert!(authenticate_client(&clients, "internal-service", "wrong").is_none());
+        assert!(authenticate_client(&clients, "other", "internal-secret").is_none());
+    }
+
+    #[test]
+    fn test_resolve_scopes() {
+        let client = client();
+        assert_eq!(resolve_scopes(&client, None).unwrap(), client.scopes);
+        assert_eq!(resolve_scopes(&client, Some("reports:read")).unwrap(), vec!["reports:read"]);
+        assert!(resolve_scopes(&client, Some("reports:read admin")).is_err());
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let mut headers = HeaderMap::new();
+        let encoded = STANDARD.encode("internal-service:internal-secret");
+        headers.insert("authorization", format!("Basic {}", encoded).parse().unwrap());
+        assert_eq!(
+            basic_credentials(&headers),
+            Some(("internal-service".to_string(), "internal-secret".to_string()))
+        );
+    }
+}
@@ -1,17 +1,27 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation, get_current_timestamp};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation, get_current_timestamp};
 use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::Algorithm;
+use redis::RedisError;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use tracing::{debug, error};
+use uuid::Uuid;
 
-#[derive(Deserialize)]
-pub struct Config {
-    pub jwt: JWTConfig,
-}
+use crate::config::jwt_config;
+use crate::metrics::metrics;
+use crate::redis_instance::RedisInstance;
+use crate::services::session_service::session_key;
+
+pub const ACCESS_TOKEN_TTL: u64 = 3600;
 
-#[derive(Deserialize)]
-pub struct JWTConfig {
-    pub secret: String,
+// Who the token was issued to: a human user or a registered machine client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Principal {
+    #[default]
+    User,
+    Client,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,65 +31,180 @@ pub struct Claims {
     pub exp: u64,
     pub iss: String,
     pub typ: String,
-    pub email: String
+    // Empty for client tokens
+    #[serde(default)]
+    pub email: String,
+    // Tokens issued before this claim existed are user tokens
+    #[serde(default)]
+    pub principal: Principal,
+    // Space separated, as in RFC 6749
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String,
+    // Unique token id, the key for revocation
+    #[serde(default)]
+    pub jti: String,
+    // Roles granted by the credential backend, e.g. from directory groups
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    // The password is past its maximum age, the token is only good for changing it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub password_expired: bool,
 }
 
-pub fn load_config() -> JWTConfig {
-    let config_value: String = std::fs::read_to_string("config.toml")
-        .expect("Unable to read config file");
-    let config: Config = toml::from_str(&config_value).expect("Unable to parse config file");
-    let jwt_config = config.jwt;
-    jwt_config
+// Audiences of emailed link tokens, they keep links from ever passing as access tokens
+// or as each other
+const MAGIC_LINK_AUDIENCE: &str = "magic_link";
+pub const EMAIL_CHANGE_AUDIENCE: &str = "email_change";
+pub const EMAIL_REVERT_AUDIENCE: &str = "email_revert";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkClaims {
+    pub sub: String, // the email the link was sent to
+    pub iat: u64,
+    pub exp: u64,
+    pub iss: String,
+    pub aud: String,
+    pub jti: String,
 }
 
-pub fn issue_jwt_token(email: &str, password: &str) -> String {
+#[derive(Debug)]
+pub enum TokenError {
+    Invalid(jsonwebtoken::errors::Error),
+    Revoked,
+    SessionEnded,
+    Store(RedisError),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenError::Invalid(e) => write!(f, "invalid token: {}", e),
+            TokenError::Revoked => write!(f, "token revoked"),
+            TokenError::SessionEnded => write!(f, "session ended"),
+            TokenError::Store(e) => write!(f, "token store error: {}", e),
+        }
+    }
+}
+
+pub fn issue_jwt_token(email: &str, roles: &[String], password_expired: bool) -> String {
     // TODO: check email and password to determine subject
-    let typ;
-    if email.ends_with("@colond.com") {
-        typ = ":D";
+    let typ = if email.ends_with("@colond.com") {
+        ":D"
     } else {
-        typ = "guest";
-    }
+        "guest"
+    };
     // TODO Validate email and password
-    debug!("[IssueToken] A user login: {} with password: {}", email, password);
-    let jwt_config = load_config();
+    debug!("[IssueToken]Issuing a token for {}", email);
+    let jwt_config = jwt_config();
     let secret = jwt_config.secret;
     // set iat to current timestamp and exp to 30 seconds later
     let iat = get_current_timestamp();
-    let exp = iat + 3600;
+    let exp = iat + ACCESS_TOKEN_TTL;
     let claims = Claims {
         sub: "Colon D Face :)".to_string(), // Subject: to what the token refers to
-        iat: iat,
-        exp: exp,
+        iat,
+        exp,
         email: email.to_owned(),
         iss: "ColonD".to_owned(), // Issuer
         typ: typ.to_owned(), // Type
+        principal: Principal::User,
+        scope: String::new(),
+        jti: Uuid::new_v4().to_string(),
+        roles: roles.to_vec(),
+        password_expired,
     };
     debug!("[IssueToken]Claims: {:?}", claims);
+    metrics().tokens_issued.with_label_values(&["user"]).inc();
+    sign_claims(&claims, secret.expose())
+}
+
+pub fn issue_client_token(client_id: &str, scopes: &[String]) -> String {
+    let jwt_config = jwt_config();
+    let secret = jwt_config.secret;
+    let iat = get_current_timestamp();
+    let claims = Claims {
+        sub: client_id.to_owned(), // Subject: the machine client itself
+        iat,
+        exp: iat + ACCESS_TOKEN_TTL,
+        email: String::new(),
+        iss: "ColonD".to_owned(),
+        typ: "client".to_owned(),
+        principal: Principal::Client,
+        scope: scopes.join(" "),
+        jti: Uuid::new_v4().to_string(),
+        roles: Vec::new(),
+        password_expired: false,
+    };
+    debug!("[IssueClientToken]Claims: {:?}", claims);
+    metrics().tokens_issued.with_label_values(&["client"]).inc();
+    sign_claims(&claims, secret.expose())
+}
+
+pub fn issue_link_token(email: &str, audience: &str, ttl: u64) -> (String, LinkClaims) {
+    let jwt_config = jwt_config();
+    let iat = get_current_timestamp();
+    let claims = LinkClaims {
+        sub: email.to_owned(),
+        iat,
+        exp: iat + ttl,
+        iss: "ColonD".to_owned(),
+        aud: audience.to_owned(),
+        jti: Uuid::new_v4().to_string(),
+    };
+    // The audience names the link kind: magic_link, email_change or email_revert
+    metrics().tokens_issued.with_label_values(&[audience]).inc();
+    (sign_claims(&claims, jwt_config.secret.expose()), claims)
+}
+
+pub fn decode_link_token(token: &str, audience: &str) -> Result<LinkClaims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&["ColonD"]);
+    validation.set_audience(&[audience]);
+    let token_data = decode_with_jwt_keys::<LinkClaims>(token, &validation)?;
+    Ok(token_data.claims)
+}
+
+pub fn issue_magic_link_token(email: &str, ttl: u64) -> (String, LinkClaims) {
+    issue_link_token(email, MAGIC_LINK_AUDIENCE, ttl)
+}
+
+pub fn decode_magic_link_token(token: &str) -> Result<LinkClaims, jsonwebtoken::errors::Error> {
+    decode_link_token(token, MAGIC_LINK_AUDIENCE)
+}
+
+// Tries the signing secret, then the previous ones kept around while a rotation is rolled out
+fn decode_with_jwt_keys<T: DeserializeOwned>(token: &str, validation: &Validation) -> Result<TokenData<T>, jsonwebtoken::errors::Error> {
+    let jwt_config = jwt_config();
+    let mut result = decode::<T>(token, &DecodingKey::from_secret(jwt_config.secret.expose().as_ref()), validation);
+    for previous in &jwt_config.previous_secrets {
+        match &result {
+            Err(e) if *e.kind() == ErrorKind::InvalidSignature => {
+                result = decode::<T>(token, &DecodingKey::from_secret(previous.expose().as_ref()), validation);
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+fn sign_claims<T: Serialize>(claims: &T, secret: &str) -> String {
     // Custom header
     let mut header = Header::new(Algorithm::HS256);
     header.typ = Some("JWT".to_owned());
-    let token = encode(
+    encode(
         &header,
-        &claims,
+        claims,
         &EncodingKey::from_secret(secret.as_ref()),
     )
-    .unwrap();
-    token
+    .unwrap()
 }
 
 pub fn get_info_from_token(_token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     // parse email from token
-    let jwt_config = load_config();
-    let secret = jwt_config.secret;
     // Use Validation to validate claims
     let mut validation = Validation::new(Algorithm::HS256);
     validation.set_issuer(&["ColonD"]);
-    let token_data = match decode::<Claims>(
-        _token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &validation,
-    ) {
+    let token_data = match decode_with_jwt_keys::<Claims>(_token, &validation) {
         Ok(c) => c.claims,
         Err(err) => match *err.kind() {
             // Return error message not panic
@@ -112,7 +237,96 @@ pub fn get_info_from_token(_token: &str) -> Result<Claims, jsonwebtoken::errors:
 
     Ok(token_data)
 }
-pub fn revoke_token(_token: &str) -> bool {
-    // TODO
-    true
+pub fn revocation_key(jti: &str) -> String {
+    format!("revoked:{}", jti)
+}
+
+// Full validation: the signature and claims checks of get_info_from_token plus the state
+// only Redis knows about, explicit revocation and (for user tokens) the login session
+pub fn validate_token(redis: &mut RedisInstance, token: &str) -> Result<Claims, TokenError> {
+    let claims = get_info_from_token(token).map_err(TokenError::Invalid)?;
+    if !claims.jti.is_empty() && redis.exists(&revocation_key(&claims.jti)).map_err(TokenError::Store)? {
+        debug!("[ValidateToken]Token {} is revoked", claims.jti);
+        return Err(TokenError::Revoked);
+    }
+    if claims.principal == Principal::User {
+        // login keeps the current token under the session key, logout removes it
+        let key = session_key(&claims.email);
+        let has_session = redis.exists(&key).map_err(TokenError::Store)?
+            && redis.get(&key).map_err(TokenError::Store)? == token;
+        if !has_session {
+            debug!("[ValidateToken]No session for {}", claims.email);
+            return Err(TokenError::SessionEnded);
+        }
+    }
+    Ok(claims)
+}
+
+// Idempotent: revoking an already revoked token just refreshes the deny-list entry
+pub fn revoke_token(redis: &mut RedisInstance, token: &str, claims: &Claims) -> Result<(), RedisError> {
+    if !claims.jti.is_empty() {
+        // Keep the entry only as long as the token could still pass signature checks
+        let ttl = claims.exp.saturating_sub(get_current_timestamp()).max(1);
+        redis.set_with_expiration(&revocation_key(&claims.jti), "1", ttl)?;
+    }
+    let key = session_key(&claims.email);
+    if claims.principal == Principal::User && redis.exists(&key)? && redis.get(&key)? == token {
+        redis.del(&key)?;
+    }
+    metrics().tokens_revoked.inc();
+    debug!("[RevokeToken]Revoked token {} of {}", claims.jti, claims.sub);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_token_is_user_principal() {
+        let token = issue_jwt_token("test@colond.com", &["admin".to_string()], false);
+        let claims = get_info_from_token(&token).unwrap();
+        assert_eq!(claims.principal, Principal::User);
+        assert_eq!(claims.email, "test@colond.com");
+        assert_eq!(claims.roles, vec!["admin"]);
+        assert!(claims.scope.is_empty());
+    }
+
+    #[test]
+    fn test_client_token_identifies_client() {
+        let scopes = vec!["reports:read".to_string(), "reports:write".to_string()];
+        let token = issue_client_token("internal-service", &scopes);
+        let claims = get_info_from_token(&token).unwrap();
+        assert_eq!(claims.principal, Principal::Client);
+        assert_eq!(claims.sub, "internal-service");
+        assert_eq!(claims.scope, "reports:read reports:write");
+        assert!(claims.email.is_empty());
+    }
+
+    #[test]
+    fn test_magic_link_token_is_not_an_access_token() {
+        let (token, issued) = issue_magic_link_token("test@colond.com", 900);
+        let claims = decode_magic_link_token(&token).unwrap();
+        assert_eq!(claims.sub, "test@colond.com");
+        assert_eq!(claims.jti, issued.jti);
+        assert!(get_info_from_token(&token).is_err());
+        let access_token = issue_jwt_token("test@colond.com", &[], false);
+        assert!(decode_magic_link_token(&access_token).is_err());
+    }
+
+    #[test]
+    fn test_link_tokens_are_bound_to_their_audience() {
+        let (token, _) = issue_link_token("new@colond.com", EMAIL_CHANGE_AUDIENCE, 900);
+        assert_eq!(decode_link_token(&token, EMAIL_CHANGE_AUDIENCE).unwrap().sub, "new@colond.com");
+        assert!(decode_link_token(&token, EMAIL_REVERT_AUDIENCE).is_err());
+        assert!(decode_magic_link_token(&token).is_err());
+    }
+
+    #[test]
+    fn test_tokens_have_unique_jti() {
+        let first = get_info_from_token(&issue_client_token("internal-service", &[])).unwrap();
+        let second = get_info_from_token(&issue_client_token("internal-service", &[])).unwrap();
+        assert!(!first.jti.is_empty());
+        assert_ne!(first.jti, second.jti);
+    }
 }
@@ -0,0 +1,37 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::{debug, warn};
+
+use crate::config::smtp_config;
+
+// Sends a plain text mail through the configured SMTP relay.
+// Without an [smtp] section the mail is dropped, so local setups work without a relay.
+pub async fn send_mail(to: &str, subject: &str, body: &str) -> Result<(), String> {
+    let config = match smtp_config() {
+        Some(config) => config,
+        None => {
+            warn!("[SendMail]No [smtp] configured, dropping mail \"{}\" to {}", subject, to);
+            return Ok(());
+        }
+    };
+    let message = Message::builder()
+        .from(config.from.parse().map_err(|e| format!("invalid from address: {}", e))?)
+        .to(to.parse().map_err(|e| format!("invalid to address: {}", e))?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())
+        .map_err(|e| e.to_string())?;
+    let mut builder = if config.starttls {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host).map_err(|e| e.to_string())?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+    };
+    builder = builder.port(config.port);
+    if let (Some(username), Some(password)) = (config.username, config.password) {
+        builder = builder.credentials(Credentials::new(username, password.expose().to_string()));
+    }
+    builder.build().send(message).await.map_err(|e| e.to_string())?;
+    debug!("[SendMail]Sent \"{}\" to {}", subject, to);
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use redis::RedisResult;
+
+use crate::redis_instance::RedisInstance;
+use crate::services::jwt_service::{issue_jwt_token, ACCESS_TOKEN_TTL};
+
+// The session key carries the email as its {hash tag}, so per-user keys added later can share its
+// Redis Cluster slot. Other keys (revoked tokens, links, OIDC state) are not tagged, never use
+// them together with a session key in MULTI or multi-key commands.
+pub fn session_key(email: &str) -> String {
+    format!("session:{{{}}}", email)
+}
+
+// One session per email: hand back the live token, or issue and store a new one.
+// An expired password always gets a fresh, restricted token in place of the live one.
+pub fn open_session(redis: &mut RedisInstance, email: &str, roles: &[String], password_expired: bool) -> RedisResult<String> {
+    let key = session_key(email);
+    if !password_expired && redis.exists(&key)? {
+        return redis.get(&key);
+    }
+    let token = issue_jwt_token(email, roles, password_expired);
+    redis.set_with_expiration(&key, &token, ACCESS_TOKEN_TTL)?;
+    Ok(token)
+}
+
+// Signs out whoever holds the session of this email, its token stops validating at once
+pub fn end_session(redis: &mut RedisInstance, email: &str) -> RedisResult<()> {
+    redis.del(&session_key(email))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_key_is_hash_tagged() {
+        let key = session_key("a@example.com");
+        assert_eq!(key, "session:{a@example.com}");
+        assert_eq!(redis::cluster_routing::get_slot(key.as_bytes()), redis::cluster_routing::get_slot(b"a@example.com"));
+    }
+}
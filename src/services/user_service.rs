@@ -0,0 +1,29 @@
+use tokio_postgres::{Error, Row};
+use uuid::Uuid;
+
+use crate::db_connection::{execute_insert_user, execute_query_user_by_email, fetch_insert_id, DbConnection};
+use crate::models::user_models::User;
+
+pub fn user_from_rows(rows: &[Row]) -> Option<User> {
+    rows.first().map(|row| User::new(
+        row.get(4),
+        row.get(0),
+        row.get(1),
+        row.get(2),
+        row.get(3),
+    ))
+}
+
+// Just-in-time provisioning for users authenticated elsewhere (an identity provider or a directory).
+// They have no local password, so store something nobody can type in, tagged with where they came from.
+pub async fn find_or_provision_user(connection: &DbConnection, email: &str, name: &str, source: &str) -> Result<(User, bool), Error> {
+    let rows = execute_query_user_by_email(connection, email).await?;
+    if let Some(user) = user_from_rows(&rows) {
+        return Ok((user, false));
+    }
+    let pwd = format!("!{}:{}", source, Uuid::new_v4());
+    let mut user = User::new(0, name.to_string(), email.to_string(), 0, pwd);
+    let rows = execute_insert_user(connection, &user).await?;
+    user._id = fetch_insert_id(&rows).await?;
+    Ok((user, true))
+}
@@ -0,0 +1,234 @@
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config as TraceConfig, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use reqwest::header::{HeaderMap, HeaderValue};
+use tracing::field::Empty;
+use tracing::{info_span, Instrument, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+use uuid::Uuid;
+
+use crate::config::TracingConfig;
+
+// Downward API variables the Deployment sets, mapped to the OpenTelemetry resource keys
+const KUBERNETES_ENV: &[(&str, &str)] = &[
+    ("POD_NAME", "k8s.pod.name"),
+    ("POD_NAMESPACE", "k8s.namespace.name"),
+    ("NODE_NAME", "k8s.node.name"),
+];
+
+// service.name and friends. OTEL_RESOURCE_ATTRIBUTES is honoured too, the config wins over it.
+fn resource(config: &TracingConfig) -> Resource {
+    let mut attributes = vec![
+        KeyValue::new("service.name", config.service_name.clone()),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ];
+    for (var, key) in KUBERNETES_ENV {
+        if let Ok(value) = std::env::var(var) {
+            if *key == "k8s.pod.name" {
+                attributes.push(KeyValue::new("service.instance.id", value.clone()));
+            }
+            attributes.push(KeyValue::new(*key, value));
+        }
+    }
+    for (key, value) in &config.resource_attributes {
+        attributes.push(KeyValue::new(key.clone(), value.clone()));
+    }
+    Resource::default().merge(&Resource::new(attributes))
+}
+
+// Exports spans in batches over OTLP/HTTP. Sampling follows the caller's decision when a
+// traceparent came in, otherwise keeps sampling_ratio of new traces.
+pub fn layer<S>(config: &TracingConfig) -> Result<OpenTelemetryLayer<S, Tracer>, TraceError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_ratio)));
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().http().with_endpoint(&config.endpoint))
+        .with_trace_config(TraceConfig::default().with_sampler(sampler).with_resource(resource(config)))
+        .install_batch(runtime::Tokio)?;
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    global::set_tracer_provider(provider);
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+// Sends the spans still buffered, call before the process exits
+pub async fn shutdown() {
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
+tokio::task_local! {
+    // X-Request-Id of the request being served
+    static REQUEST_ID: String;
+}
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// The id of the current request, None outside of one (startup, background tasks)
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// A caller's id is kept when it is short and plain enough to print in a log line as is
+fn accepted_request_id(request: &Request) -> Option<String> {
+    let id = request.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_.:".contains(c);
+    (!id.is_empty() && id.len() <= 128 && id.chars().all(plain)).then(|| id.to_string())
+}
+
+// Keeps the caller's X-Request-Id or makes one up, and answers with it.
+// Outermost, so every span, log line and response body of the request can use it.
+pub async fn assign_request_id(request: Request, next: Next) -> Response {
+    let id = accepted_request_id(&request).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// One server span per request, continuing the trace of an incoming traceparent
+pub async fn trace_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string()).unwrap_or_else(|| "unmatched".to_string());
+    let span = info_span!(
+        "http.request",
+        otel.name = %format!("{} {}", method, route),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = %method,
+        http.route = %route,
+        url.path = %request.uri().path(),
+        http.response.status_code = Empty,
+        request_id = Empty,
+    );
+    if let Some(id) = request_id() {
+        span.record("request_id", id);
+    }
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+    span.set_parent(parent);
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+    response
+}
+
+// Client span around one Postgres statement, named after the execute_* function
+pub fn db_span(name: &'static str, query: &str) -> Span {
+    info_span!(
+        "db.query",
+        otel.name = name,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        db.system = "postgresql",
+        db.operation.name = name,
+        db.query.text = query,
+    )
+}
+
+// Client span around one Redis command
+pub fn redis_span(command: &'static str) -> Span {
+    info_span!(
+        "redis.command",
+        otel.name = command,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        db.system = "redis",
+        db.operation.name = command,
+    )
+}
+
+pub fn record_result<T, E>(span: &Span, result: &Result<T, E>) {
+    if result.is_err() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+// traceparent (and tracestate) of the current span and the X-Request-Id, for calls to other services
+pub fn trace_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(&mut headers)));
+    if let Some(value) = request_id().and_then(|id| HeaderValue::from_str(&id).ok()) {
+        headers.insert(REQUEST_ID_HEADER, value);
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::middleware;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use serde_json::json;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::models::user_models::CommonResponse;
+
+    #[tokio::test]
+    async fn test_traceparent_is_continued_and_passed_on() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = TracerProvider::builder().build().tracer("test");
+        let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // Echoes the traceparent it would send to the next service
+        let app = Router::new()
+            .route("/outgoing", get(|| async { trace_headers()["traceparent"].to_str().unwrap().to_string() }))
+            .layer(middleware::from_fn(trace_http));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/outgoing", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let incoming = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let client = reqwest::Client::new();
+        let outgoing = client.get(&url).header("traceparent", incoming).send().await.unwrap().text().await.unwrap();
+        let parts: Vec<&str> = outgoing.split('-').collect();
+        assert_eq!(parts[1], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(parts[2], "00f067aa0ba902b7");
+        assert_eq!(parts[3], "01");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_kept_or_made_up() {
+        // Answers with the envelope and the X-Request-Id it would send to the next service
+        let app = Router::new()
+            .route("/outgoing", get(|| async {
+                let forwarded = trace_headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+                Json(CommonResponse::success("ok".to_string(), json!({ "forwarded": forwarded })))
+            }))
+            .layer(middleware::from_fn(trace_http))
+            .layer(middleware::from_fn(assign_request_id));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/outgoing", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).header(REQUEST_ID_HEADER, "req-42").send().await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-42");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["request_id"], "req-42");
+        assert_eq!(body["data"]["forwarded"], "req-42");
+
+        // Anything that could forge a log line is replaced
+        let response = client.get(&url).header(REQUEST_ID_HEADER, "a b\"c").send().await.unwrap();
+        let made_up = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        assert!(Uuid::parse_str(&made_up).is_ok());
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["request_id"], made_up.as_str());
+    }
+}
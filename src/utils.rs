@@ -1 +1,4 @@
-pub mod sha256_util;
\ No newline at end of file
+pub mod sha256_util;
+pub mod cookie_util;
+pub mod password_util;
+pub mod validation_util;
@@ -0,0 +1,32 @@
+use axum::http::HeaderMap;
+
+pub fn get_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+// HttpOnly so scripts cannot read it, Lax so it survives the redirect back from another site
+pub fn build_cookie(name: &str, value: &str, path: &str, max_age: u64) -> String {
+    format!("{}={}; Path={}; Max-Age={}; HttpOnly; Secure; SameSite=Lax", name, value, path, max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", "a=1; oidc_state=abc".parse().unwrap());
+        headers.append("cookie", "b=2".parse().unwrap());
+        assert_eq!(get_cookie(&headers, "oidc_state"), Some("abc".to_string()));
+        assert_eq!(get_cookie(&headers, "b"), Some("2".to_string()));
+        assert_eq!(get_cookie(&headers, "missing"), None);
+    }
+}
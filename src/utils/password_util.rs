@@ -0,0 +1,77 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Unable to hash password")
+        .to_string()
+}
+
+// False for anything that is not a valid argon2 PHC string
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Rows created before hashing hold the plain password, provisioned accounts hold a "!source:..." marker
+pub fn is_password_hash(stored: &str) -> bool {
+    stored.starts_with("$argon2")
+}
+
+// Checks a password against the pwd column, accepting legacy plain text rows until they are upgraded
+pub fn check_stored_password(password: &str, stored: &str) -> bool {
+    if is_password_hash(stored) {
+        return verify_password(password, stored);
+    }
+    !stored.is_empty() && !stored.starts_with('!') && stored == password
+}
+
+// Argon2 costs tens of milliseconds of CPU, so request handlers run it on the blocking pool
+pub async fn hash_password_async(password: &str) -> String {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .expect("Password hashing task panicked")
+}
+
+pub async fn check_stored_password_async(password: &str, stored: &str) -> bool {
+    let (password, stored) = (password.to_string(), stored.to_string());
+    tokio::task::spawn_blocking(move || check_stored_password(&password, &stored))
+        .await
+        .expect("Password check task panicked")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hash = hash_password("correct horse");
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", ""));
+    }
+
+    #[test]
+    fn test_check_stored_password() {
+        assert!(check_stored_password("legacy", "legacy"));
+        assert!(!check_stored_password("legacy", "other"));
+        assert!(check_stored_password("hashed", &hash_password("hashed")));
+        assert!(!check_stored_password("!oidc:abc", "!oidc:abc"));
+        assert!(!check_stored_password("", ""));
+    }
+
+    #[tokio::test]
+    async fn test_async_hash_and_check() {
+        let hash = hash_password_async("correct horse").await;
+        assert!(check_stored_password_async("correct horse", &hash).await);
+        assert!(!check_stored_password_async("wrong horse", &hash).await);
+    }
+}
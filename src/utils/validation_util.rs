@@ -0,0 +1,122 @@
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::{async_trait, Json};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use validator::{Validate, ValidationErrors};
+
+use crate::models::user_models::{CommonResponse, FieldError};
+
+// Json that also runs the #[validate(...)] rules of the request struct. Both malformed bodies
+// and rule violations are answered in the CommonResponse envelope, the latter as one 422 with every field error.
+pub struct ValidJson<T>(pub T);
+
+// Like ValidJson, but hands the field errors to the handler, which adds the ones only it can
+// find (the password policy needs the database) and answers them all in one validation_failed
+pub struct CheckedJson<T>(pub T, pub Vec<FieldError>);
+
+fn parse_rejection(rejection: JsonRejection) -> (StatusCode, Json<CommonResponse>) {
+    let status = rejection.status();
+    let response = CommonResponse::new(rejection.body_text(), status.as_u16().to_string(), json!({}));
+    (status, Json(response))
+}
+
+// The one 422 every rule violation is answered with
+pub fn validation_failed(mut errors: Vec<FieldError>) -> (StatusCode, Json<CommonResponse>) {
+    errors.sort_by(|a, b| a.field.cmp(&b.field));
+    let status = StatusCode::UNPROCESSABLE_ENTITY;
+    let response = CommonResponse::new("request validation failed".to_string(), status.as_u16().to_string(), json!({ "errors": errors }));
+    (status, Json(response))
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<CommonResponse>);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await.map_err(parse_rejection)?;
+        value.validate().map_err(|e| validation_failed(field_errors(&e)))?;
+        Ok(ValidJson(value))
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for CheckedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<CommonResponse>);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await.map_err(parse_rejection)?;
+        let errors = value.validate().err().map(|e| field_errors(&e)).unwrap_or_default();
+        Ok(CheckedJson(value, errors))
+    }
+}
+
+// Flattens validator's errors into ours, sorted by field so responses are stable
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields: Vec<_> = errors.field_errors().into_iter().collect();
+    fields.sort_by_key(|(field, _)| *field);
+    fields
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |e| {
+                let message = e.message.clone().map(|m| m.to_string()).unwrap_or_else(|| e.code.to_string());
+                FieldError::new(field, &e.code, message)
+            })
+        })
+        .collect()
+}
+
+// For #[serde(deserialize_with = "trimmed")], so rules see the value without surrounding whitespace
+pub fn trimmed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(value.trim().to_string())
+}
+
+// The one form of an email used for lookups, the unique index, tokens and Redis keys
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// For #[serde(deserialize_with = "normalized_email")] on every email a client sends
+pub fn normalized_email<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(normalize_email(&value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user_models::{CreateUserRequest, UserLoginRequest};
+
+    #[test]
+    fn test_every_field_error_is_reported() {
+        let body = json!({ "name": "   ", "email": "not-an-email", "age": -1, "pwd": "x" });
+        let req: CreateUserRequest = serde_json::from_value(body).unwrap();
+        let errors = field_errors(&req.validate().unwrap_err());
+        let fields: Vec<(&str, &str)> = errors.iter().map(|e| (e.field.as_str(), e.rule.as_str())).collect();
+        assert_eq!(fields, vec![("age", "range"), ("email", "email"), ("name", "length")]);
+        assert!(errors.iter().all(|e| !e.message.is_empty()));
+    }
+
+    #[test]
+    fn test_values_are_trimmed() {
+        let body = json!({ "name": " Alice ", "email": " Alice@Example.COM\n", "age": 30, "pwd": " keep spaces " });
+        let req: CreateUserRequest = serde_json::from_value(body).unwrap();
+        assert!(req.validate().is_ok());
+        assert_eq!(req.name, "Alice");
+        assert_eq!(req.email, "alice@example.com");
+        assert_eq!(req.pwd.expose(), " keep spaces ");
+        let login: UserLoginRequest = serde_json::from_value(json!({ "email": "ALICE@example.com ", "pwd": "x" })).unwrap();
+        assert_eq!(login.email, "alice@example.com");
+    }
+}